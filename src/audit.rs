@@ -0,0 +1,296 @@
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::clients::{ClientId, ClientPool};
+use crate::transactions::management::TransactionTree;
+use crate::transactions::processing::ProcessingEvent;
+use crate::transactions::{TransactionId, TransactionType};
+
+// The money a client's accepted transactions moved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientFlows {
+    pub deposits: Decimal,
+    pub withdrawals: Decimal,
+    // what chargebacks took off the total, a charged back withdrawal gives
+    // the amount back so it counts as negative
+    pub chargebacks: Decimal,
+    // withdrawals with an open dispute, their amount is credited back as
+    // held funds until the dispute is resolved or charged back
+    pub disputed_withdrawals: Decimal,
+}
+
+impl ClientFlows {
+    pub fn expected_total(&self) -> Decimal {
+        self.deposits - self.withdrawals - self.chargebacks + self.disputed_withdrawals
+    }
+}
+
+// Adds up the accepted transactions from processing events, independently
+// of the client balances, so the two can be compared afterwards
+#[derive(Debug, Default)]
+pub struct Ledger {
+    flows: BTreeMap<ClientId, ClientFlows>,
+    // the referenced transaction decides which way a chargeback goes, that
+    // is looked up at audit time
+    chargebacks: Vec<(ClientId, TransactionId, Decimal)>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, event: &ProcessingEvent) {
+        let transaction = &event.transaction;
+        // replays were acknowledged, but nothing was applied
+        let amount = match event.amount {
+            Some(amount) if !event.replay => amount,
+            _ => return,
+        };
+
+        let flows = self.flows.entry(transaction.client_id).or_default();
+        match transaction.tx_type {
+            TransactionType::Deposit => flows.deposits += amount,
+            // the captured part of an authorization leaves the account like a
+            // withdrawal, authorizing and voiding only move it around
+            TransactionType::Withdrawal | TransactionType::Capture => flows.withdrawals += amount,
+            TransactionType::Chargeback if amount != Decimal::ZERO => {
+                self.chargebacks
+                    .push((transaction.client_id, transaction.tx_id, amount))
+            }
+            // disputes and resolves only move funds between available and held
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditDiscrepancy {
+    // the client's total doesn't match its accepted transactions
+    Total {
+        client_id: ClientId,
+        expected: Decimal,
+        actual: Decimal,
+    },
+    // the client's held funds don't match its open disputes
+    Held {
+        client_id: ClientId,
+        expected: Decimal,
+        actual: Decimal,
+    },
+    // the sum of all totals doesn't match all accepted transactions
+    Ledger {
+        expected: Decimal,
+        actual: Decimal,
+    },
+    // a chargeback whose transaction isn't retained, so its direction is unknown
+    UnknownChargeback(TransactionId),
+}
+
+impl fmt::Display for AuditDiscrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuditDiscrepancy::Total {
+                client_id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "client {}: total expected {:.4}, actual {:.4}, delta {:+.4}",
+                client_id,
+                expected,
+                actual,
+                actual - expected
+            ),
+            AuditDiscrepancy::Held {
+                client_id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "client {}: held expected {:.4} from open disputes, actual {:.4}, delta {:+.4}",
+                client_id,
+                expected,
+                actual,
+                actual - expected
+            ),
+            AuditDiscrepancy::Ledger { expected, actual } => write!(
+                f,
+                "ledger: sum of totals expected {:.4}, actual {:.4}, delta {:+.4}",
+                expected,
+                actual,
+                actual - expected
+            ),
+            AuditDiscrepancy::UnknownChargeback(tx_id) => write!(
+                f,
+                "chargeback for transaction {:?} can't be audited, the transaction isn't retained",
+                tx_id
+            ),
+        }
+    }
+}
+
+// One row of the trial balance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrialBalanceLine {
+    pub client_id: ClientId,
+    pub flows: ClientFlows,
+    pub total: Decimal,
+    pub held: Decimal,
+    // the sum of the client's open disputes
+    pub disputed: Decimal,
+}
+
+#[derive(Debug, Default)]
+pub struct AuditReport {
+    pub lines: Vec<TrialBalanceLine>,
+    pub discrepancies: Vec<AuditDiscrepancy>,
+}
+
+impl AuditReport {
+    pub fn is_clean(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+
+    // the sums of every column of the trial balance
+    pub fn totals(&self) -> (ClientFlows, Decimal, Decimal, Decimal) {
+        let mut flows = ClientFlows::default();
+        let (mut total, mut held, mut disputed) = (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO);
+        for line in &self.lines {
+            flows.deposits += line.flows.deposits;
+            flows.withdrawals += line.flows.withdrawals;
+            flows.chargebacks += line.flows.chargebacks;
+            flows.disputed_withdrawals += line.flows.disputed_withdrawals;
+            total += line.total;
+            held += line.held;
+            disputed += line.disputed;
+        }
+        (flows, total, held, disputed)
+    }
+}
+
+impl fmt::Display for AuditReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "trial balance:")?;
+        writeln!(
+            f,
+            "client, deposits, withdrawals, chargebacks, disputed_withdrawals, expected_total, total, held, disputed"
+        )?;
+        for line in &self.lines {
+            writeln!(
+                f,
+                "{}, {:.4}, {:.4}, {:.4}, {:.4}, {:.4}, {:.4}, {:.4}, {:.4}",
+                line.client_id,
+                line.flows.deposits,
+                line.flows.withdrawals,
+                line.flows.chargebacks,
+                line.flows.disputed_withdrawals,
+                line.flows.expected_total(),
+                line.total,
+                line.held,
+                line.disputed
+            )?;
+        }
+        let (flows, total, held, disputed) = self.totals();
+        writeln!(
+            f,
+            "all, {:.4}, {:.4}, {:.4}, {:.4}, {:.4}, {:.4}, {:.4}, {:.4}",
+            flows.deposits,
+            flows.withdrawals,
+            flows.chargebacks,
+            flows.disputed_withdrawals,
+            flows.expected_total(),
+            total,
+            held,
+            disputed
+        )?;
+
+        writeln!(
+            f,
+            "audit: {} clients, {} discrepancies",
+            self.lines.len(),
+            self.discrepancies.len()
+        )?;
+        for discrepancy in &self.discrepancies {
+            writeln!(f, "  {}", discrepancy)?;
+        }
+        Ok(())
+    }
+}
+
+// Checks the whole ledger for conservation: every client's total has to be
+// what its accepted transactions add up to, and its held funds have to be
+// what its open disputes add up to
+pub fn audit(ledger: &Ledger, clients: &ClientPool, tree: &TransactionTree) -> AuditReport {
+    let mut report = AuditReport::default();
+    let mut flows = ledger.flows.clone();
+
+    for (client_id, tx_id, amount) in &ledger.chargebacks {
+        let entry = flows.entry(*client_id).or_default();
+        match tree.get(tx_id).map(|transaction| transaction.tx_type) {
+            Some(TransactionType::Deposit) => entry.chargebacks += amount,
+            Some(TransactionType::Withdrawal) => entry.chargebacks -= amount,
+            _ => report
+                .discrepancies
+                .push(AuditDiscrepancy::UnknownChargeback(*tx_id)),
+        }
+    }
+
+    let mut disputed: BTreeMap<ClientId, Decimal> = BTreeMap::new();
+    for transaction in tree.open_disputes() {
+        let amount = transaction.amount.unwrap_or_default();
+        *disputed.entry(transaction.client_id).or_default() += amount;
+        if transaction.tx_type == TransactionType::Withdrawal {
+            flows
+                .entry(transaction.client_id)
+                .or_default()
+                .disputed_withdrawals += amount;
+        }
+    }
+
+    // every client that has a balance, moved money, or has an open dispute
+    let client_ids: BTreeSet<ClientId> = clients
+        .iter()
+        .map(|client| client.id)
+        .chain(flows.keys().copied())
+        .chain(disputed.keys().copied())
+        .collect();
+
+    for client_id in client_ids {
+        let client = clients.get_client(client_id);
+        let line = TrialBalanceLine {
+            client_id,
+            flows: flows.get(&client_id).copied().unwrap_or_default(),
+            total: client.map(|client| client.total).unwrap_or_default(),
+            held: client.map(|client| client.held).unwrap_or_default(),
+            disputed: disputed.get(&client_id).copied().unwrap_or_default(),
+        };
+
+        if line.flows.expected_total() != line.total {
+            report.discrepancies.push(AuditDiscrepancy::Total {
+                client_id,
+                expected: line.flows.expected_total(),
+                actual: line.total,
+            });
+        }
+        if line.disputed != line.held {
+            report.discrepancies.push(AuditDiscrepancy::Held {
+                client_id,
+                expected: line.disputed,
+                actual: line.held,
+            });
+        }
+        report.lines.push(line);
+    }
+
+    let (flows, total, _, _) = report.totals();
+    if flows.expected_total() != total {
+        report.discrepancies.push(AuditDiscrepancy::Ledger {
+            expected: flows.expected_total(),
+            actual: total,
+        });
+    }
+
+    report
+}
@@ -0,0 +1,84 @@
+use std::io::Write;
+use std::process;
+
+use tps::generator::{generate, GeneratorConfig};
+
+const USAGE: &str = "incorrect usage of the interface, please provide options in the format 'cargo run --bin tps-gen -- [--seed <seed>] [--rows <rows>] [--clients <clients>] [--deposit-share <share>] [--dispute-rate <rate>] [--resolve-rate <rate>] [--chargeback-rate <rate>] [--duplicate-rate <rate>] [--malformed-rate <rate>] [--max-amount <amount>] [--output <transactions.csv>] [--expected <expected_accounts.csv>]'";
+
+struct Args {
+    config: GeneratorConfig,
+    output_filename: Option<String>,
+    expected_filename: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Option<Args> {
+    let mut config = GeneratorConfig::default();
+    let mut output_filename = None;
+    let mut expected_filename = None;
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--seed" => config.seed = iter.next()?.parse().ok()?,
+            "--rows" => config.rows = iter.next()?.parse().ok()?,
+            "--clients" => config.clients = iter.next()?.parse().ok()?,
+            "--deposit-share" => config.deposit_share = iter.next()?.parse().ok()?,
+            "--dispute-rate" => config.dispute_rate = iter.next()?.parse().ok()?,
+            "--resolve-rate" => config.resolve_rate = iter.next()?.parse().ok()?,
+            "--chargeback-rate" => config.chargeback_rate = iter.next()?.parse().ok()?,
+            "--duplicate-rate" => config.duplicate_rate = iter.next()?.parse().ok()?,
+            "--malformed-rate" => config.malformed_rate = iter.next()?.parse().ok()?,
+            "--max-amount" => config.max_amount = iter.next()?.parse().ok()?,
+            "--output" => output_filename = Some(iter.next()?.clone()),
+            "--expected" => expected_filename = Some(iter.next()?.clone()),
+            _ => return None,
+        }
+    }
+
+    Some(Args {
+        config,
+        output_filename,
+        expected_filename,
+    })
+}
+
+fn run(args: &Args) -> anyhow::Result<()> {
+    let workload = generate(&args.config)?;
+
+    // the transactions go to stdout unless a file was given
+    match &args.output_filename {
+        Some(output_filename) => workload.write_csv(std::io::BufWriter::new(
+            std::fs::File::create(output_filename)?,
+        ))?,
+        None => {
+            let stdout = std::io::stdout();
+            let mut stdout = stdout.lock();
+            workload.write_csv(&mut stdout)?;
+            stdout.flush()?;
+        }
+    }
+
+    if let Some(expected_filename) = &args.expected_filename {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(expected_filename)?);
+        workload.write_expected(&mut file)?;
+        file.flush()?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let args_vec: Vec<String> = std::env::args().collect();
+
+    let args = match parse_args(&args_vec) {
+        Some(args) => args,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = run(&args) {
+        eprintln!("could not generate transactions due to: {}", e);
+        process::exit(1);
+    }
+}
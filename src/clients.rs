@@ -1,20 +1,240 @@
 use anyhow::Result;
 use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{self, Write};
+use std::io;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
 
-// allow for copying, equality testing and sorting
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-pub struct ClientId(u16);
+use crate::policy::EnginePolicy;
+use crate::transactions::{to_4dp, Transaction, TransactionId, TransactionType};
+
+// Either a number or a name, read from the text of the client column. Text
+// made only of digits that fits a u64 is a number, leading zeros included,
+// so `007` and `7` are the same id and both print as `7`. Anything else is
+// a name, and two names are the same only when their text is. Numbers sort
+// before names, numbers in numeric order and names in string order.
+//
+// Names are interned so ids stay `Copy` and cheap to compare. Each distinct
+// name is kept for the rest of the process.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ClientId(Repr);
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Repr {
+    Number(u64),
+    Name(&'static str),
+}
+
+impl ClientId {
+    // Not a `From` impl, so `ClientId::from(2)` keeps meaning a u16
+    pub fn from_u64(id: u64) -> Self {
+        Self(Repr::Number(id))
+    }
+
+    pub fn number(&self) -> Option<u64> {
+        match self.0 {
+            Repr::Number(number) => Some(number),
+            Repr::Name(_) => None,
+        }
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        match self.0 {
+            Repr::Number(_) => None,
+            Repr::Name(name) => Some(name),
+        }
+    }
+
+    // For self-describing formats like JSON, where numeric ids are written as
+    // numbers rather than text
+    pub fn deserialize_any<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ClientIdVisitor)
+    }
+}
 
 // Enables printing
 impl fmt::Display for ClientId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+        match self.0 {
+            Repr::Number(number) => write!(f, "{}", number),
+            Repr::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl fmt::Debug for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Repr::Number(number) => write!(f, "ClientId({})", number),
+            Repr::Name(name) => write!(f, "ClientId({:?})", name),
+        }
+    }
+}
+
+impl From<u16> for ClientId {
+    fn from(id: u16) -> Self {
+        Self(Repr::Number(u64::from(id)))
+    }
+}
+
+impl FromStr for ClientId {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        if text.is_empty() {
+            return Err(anyhow::anyhow!("client id is empty"));
+        }
+        // digits are a number even with leading zeros, so `007` is client 7.
+        // Numbers too large for a u64 are kept as names, so the id kind can
+        // say they are too large
+        let digits = text.bytes().all(|byte| byte.is_ascii_digit());
+        match text.parse() {
+            Ok(number) if digits => Ok(Self(Repr::Number(number))),
+            _ => Ok(Self(Repr::Name(intern(text)))),
+        }
+    }
+}
+
+impl Serialize for ClientId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Repr::Number(number) => serializer.serialize_u64(number),
+            Repr::Name(name) => serializer.serialize_str(name),
+        }
+    }
+}
+
+// Read from the text of the field, so CSV files get the same ids as `from_str`
+impl<'de> Deserialize<'de> for ClientId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(ClientIdVisitor)
+    }
+}
+
+struct ClientIdVisitor;
+
+impl Visitor<'_> for ClientIdVisitor {
+    type Value = ClientId;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a client id")
+    }
+
+    fn visit_u64<E: de::Error>(self, number: u64) -> Result<ClientId, E> {
+        Ok(ClientId::from_u64(number))
+    }
+
+    fn visit_str<E: de::Error>(self, text: &str) -> Result<ClientId, E> {
+        text.parse().map_err(E::custom)
+    }
+}
+
+fn intern(name: &str) -> &'static str {
+    static NAMES: OnceLock<Mutex<BTreeSet<&'static str>>> = OnceLock::new();
+
+    let mut names = NAMES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(interned) = names.get(name) {
+        return interned;
+    }
+    let interned: &'static str = Box::leak(name.to_owned().into_boxed_str());
+    names.insert(interned);
+    interned
+}
+
+// The client ids an input is allowed to have. `u16` is the default and keeps
+// the original limit of 65,536 clients.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClientIdKind {
+    #[default]
+    #[serde(rename = "u16")]
+    U16,
+    #[serde(rename = "u64")]
+    U64,
+    // any text, numeric ids are still allowed
+    #[serde(rename = "string")]
+    String,
+}
+
+impl fmt::Display for ClientIdKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientIdKind::U16 => write!(f, "u16"),
+            ClientIdKind::U64 => write!(f, "u64"),
+            ClientIdKind::String => write!(f, "string"),
+        }
+    }
+}
+
+impl FromStr for ClientIdKind {
+    type Err = anyhow::Error;
+
+    fn from_str(kind: &str) -> Result<Self> {
+        match kind {
+            "u16" => Ok(ClientIdKind::U16),
+            "u64" => Ok(ClientIdKind::U64),
+            "string" => Ok(ClientIdKind::String),
+            other => Err(anyhow::anyhow!("unknown client id type '{}'", other)),
+        }
+    }
+}
+
+impl ClientIdKind {
+    pub fn check(&self, client_id: ClientId) -> Result<(), ClientIdError> {
+        let fits = match (self, client_id.0) {
+            (ClientIdKind::String, _) => true,
+            (ClientIdKind::U64, Repr::Number(_)) => true,
+            (ClientIdKind::U16, Repr::Number(number)) => number <= u64::from(u16::MAX),
+            (_, Repr::Name(_)) => false,
+        };
+        match fits {
+            true => Ok(()),
+            false => Err(ClientIdError {
+                client_id,
+                kind: *self,
+            }),
+        }
+    }
+}
+
+// A client id that doesn't fit the configured `ClientIdKind`
+#[derive(Debug)]
+pub struct ClientIdError {
+    pub client_id: ClientId,
+    pub kind: ClientIdKind,
+}
+
+impl fmt::Display for ClientIdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = self.client_id.to_string();
+        // a name made only of digits is a number that overflowed a u64
+        let too_large = match self.client_id.0 {
+            Repr::Number(_) => true,
+            Repr::Name(name) => name.bytes().all(|byte| byte.is_ascii_digit()),
+        };
+        if too_large {
+            write!(
+                f,
+                "client id {} is too large for {} client ids",
+                text, self.kind
+            )
+        } else {
+            write!(
+                f,
+                "client id '{}' is not a number, it needs string client ids",
+                text
+            )
+        }
     }
 }
 
+impl std::error::Error for ClientIdError {}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Client {
     pub id: ClientId,
@@ -22,6 +242,10 @@ pub struct Client {
     pub held: Decimal,
     pub total: Decimal,
     pub locked: bool,
+    // how far below zero withdrawals can take the balances
+    pub overdraft_limit: Decimal,
+    // authorized but not yet captured or voided, part of the total like held
+    pub pending: Decimal,
 }
 
 impl Client {
@@ -32,6 +256,8 @@ impl Client {
             held: Decimal::new(0, 4),
             total: Decimal::new(0, 4),
             locked: false,
+            overdraft_limit: Decimal::new(0, 4),
+            pending: Decimal::new(0, 4),
         }
     }
 
@@ -48,24 +274,33 @@ impl Client {
             held,
             total,
             locked,
+            overdraft_limit: Decimal::new(0, 4),
+            pending: Decimal::new(0, 4),
         }
     }
 
     pub fn check_client_validity(&self) -> bool {
         let zero_val = Decimal::new(0, 4);
+        // available and total can go down to the overdraft floor, held can't
+        let floor = -self.overdraft_limit;
 
-        let available_amount = self.total - self.held;
-        if self.available < zero_val || available_amount != self.available {
+        let available_amount = self.total - self.held - self.pending;
+        if self.available < floor || available_amount != self.available {
             return false;
         }
 
-        let held_amount = self.total - self.available;
+        let held_amount = self.total - self.available - self.pending;
         if self.held < zero_val || held_amount != self.held {
             return false;
         }
 
-        let total_amount = self.available + self.held;
-        if self.total < zero_val || total_amount != self.total {
+        // pending funds can't be negative either
+        if self.pending < zero_val {
+            return false;
+        }
+
+        let total_amount = self.available + self.held + self.pending;
+        if self.total < floor || total_amount != self.total {
             return false;
         }
 
@@ -73,12 +308,108 @@ impl Client {
     }
 }
 
+// One row of a client-profile file, the settings a client gets when it is
+// created and what is known about the client. Every column but the client is
+// optional. The details are carried through to statements and the JSON
+// account output, the tier picks the client's settings from the policy.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClientProfile {
+    #[serde(rename = "client", skip_serializing)]
+    pub client_id: ClientId,
+    // the tier's overdraft limit applies when this is left empty
+    #[serde(default)]
+    pub overdraft_limit: Option<Decimal>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub tier: Option<String>,
+    // when the account was opened, as YYYY-MM-DD
+    #[serde(default)]
+    pub created: Option<String>,
+    #[serde(default)]
+    pub risk_rating: Option<String>,
+}
+
+impl ClientProfile {
+    // A profile with nothing in it
+    pub fn new(client_id: ClientId) -> Self {
+        Self {
+            client_id,
+            overdraft_limit: None,
+            name: None,
+            tier: None,
+            created: None,
+            risk_rating: None,
+        }
+    }
+}
+
+pub fn read_client_profiles(filename: &str) -> Result<BTreeMap<ClientId, ClientProfile>> {
+    let file = std::fs::File::open(filename)?;
+    let buf = io::BufReader::new(file);
+
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(buf);
+
+    let mut profiles = BTreeMap::new();
+
+    for result in reader.deserialize() {
+        let profile: ClientProfile = result?;
+        let client_id = profile.client_id;
+        if profile
+            .overdraft_limit
+            .is_some_and(|limit| limit < Decimal::ZERO)
+        {
+            return Err(anyhow::anyhow!(
+                "client {} has a negative overdraft limit",
+                client_id
+            ));
+        }
+        if let Some(created) = &profile.created {
+            if !is_date(created) {
+                return Err(anyhow::anyhow!(
+                    "client {} has a created date '{}' that isn't YYYY-MM-DD",
+                    client_id,
+                    created
+                ));
+            }
+        }
+        if profiles.insert(client_id, profile).is_some() {
+            return Err(anyhow::anyhow!(
+                "client {} appears more than once in profile file",
+                client_id
+            ));
+        }
+    }
+
+    Ok(profiles)
+}
+
+// Only the shape of the date is checked, 2024-02-31 passes
+fn is_date(date: &str) -> bool {
+    let parts: Vec<&str> = date.split('-').collect();
+    let number = |part: &str, len: usize| {
+        part.len() == len && part.bytes().all(|byte| byte.is_ascii_digit())
+    };
+    match parts.as_slice() {
+        [year, month, day] if number(year, 4) && number(month, 2) && number(day, 2) => {
+            matches!(month.parse::<u8>(), Ok(1..=12)) && matches!(day.parse::<u8>(), Ok(1..=31))
+        }
+        _ => false,
+    }
+}
+
 // Holds a BTreeMap of ClientId to Client
 // If this was in a concurrent/mutli-threaded environment, this would be an
 // Arc<Mutex<BTreeMap<ClientId, Client>>>
 #[derive(Debug)]
 pub struct ClientPool {
     clients: BTreeMap<ClientId, Client>,
+    profiles: BTreeMap<ClientId, ClientProfile>,
+    // open authorizations with a timestamp, oldest first, so the expired
+    // ones can be found without going through the transactions
+    authorizations: BTreeSet<(u64, TransactionId, ClientId)>,
 }
 
 impl Default for ClientPool {
@@ -89,9 +420,47 @@ impl Default for ClientPool {
 
 impl ClientPool {
     pub fn new() -> Self {
+        Self::with_profiles(BTreeMap::new())
+    }
+
+    pub fn with_profiles(profiles: BTreeMap<ClientId, ClientProfile>) -> Self {
         Self {
             clients: BTreeMap::new(),
+            profiles,
+            authorizations: BTreeSet::new(),
+        }
+    }
+
+    // A new client with the settings from its profile, if it has one, and
+    // from its tier for anything the profile leaves empty. It isn't added to
+    // the pool.
+    pub fn new_client(&self, client_id: ClientId, policy: &EnginePolicy) -> Client {
+        let mut client = Client::new(client_id);
+        if let Some(profile) = self.profiles.get(&client_id) {
+            let tier = policy.tier(profile.tier.as_deref());
+            if let Some(limit) = profile
+                .overdraft_limit
+                .or_else(|| tier.and_then(|tier| tier.overdraft_limit))
+            {
+                client.overdraft_limit = limit;
+            }
         }
+        client
+    }
+
+    // Adds a new client with the settings from its profile, if it has one
+    pub fn create_client(&mut self, client_id: ClientId, policy: &EnginePolicy) {
+        let client = self.new_client(client_id, policy);
+        self.add_client(client);
+    }
+
+    pub fn get_profile(&self, client_id: ClientId) -> Option<&ClientProfile> {
+        self.profiles.get(&client_id)
+    }
+
+    // The tier from the client's profile, if it has one
+    pub fn get_tier(&self, client_id: ClientId) -> Option<&str> {
+        self.profiles.get(&client_id)?.tier.as_deref()
     }
 
     pub fn add_client(&mut self, client: Client) {
@@ -110,7 +479,92 @@ impl ClientPool {
         self.clients.get_mut(&client_id)
     }
 
+    // Keeps track of an authorization that was written to the store, while
+    // it is open. Authorizations without a timestamp never expire.
+    pub(crate) fn track_authorization(&mut self, transaction: &Transaction) {
+        let Some(timestamp) = transaction.timestamp else {
+            return;
+        };
+        if transaction.tx_type != TransactionType::Authorize || transaction.failure.is_some() {
+            return;
+        }
+        let key = (timestamp, transaction.tx_id, transaction.client_id);
+        if transaction.settlement.is_none() {
+            self.authorizations.insert(key);
+        } else {
+            self.authorizations.remove(&key);
+        }
+    }
+
+    // The open authorizations made before `cutoff`, oldest first, as
+    // (timestamp, tx id, client id)
+    pub(crate) fn authorizations_before(&self, cutoff: u64) -> Vec<(u64, TransactionId, ClientId)> {
+        self.authorizations
+            .iter()
+            .take_while(|(timestamp, _, _)| *timestamp < cutoff)
+            .copied()
+            .collect()
+    }
+
+    // Stops tracking an authorization that can't be expired
+    pub(crate) fn forget_authorization(&mut self, key: (u64, TransactionId, ClientId)) {
+        self.authorizations.remove(&key);
+    }
+
+    // A pool with copies of just the given clients, their profiles and open
+    // authorizations, for trying out transactions without touching this one
+    pub(crate) fn scratch(&self, client_ids: &BTreeSet<ClientId>) -> ClientPool {
+        let copied = |id: &ClientId| self.clients.get(id).map(|client| (*id, *client));
+        let profile = |id: &ClientId| self.profiles.get(id).map(|profile| (*id, profile.clone()));
+        ClientPool {
+            clients: client_ids.iter().filter_map(copied).collect(),
+            profiles: client_ids.iter().filter_map(profile).collect(),
+            authorizations: self
+                .authorizations
+                .iter()
+                .filter(|(_, _, client_id)| client_ids.contains(client_id))
+                .copied()
+                .collect(),
+        }
+    }
+
+    // Takes over the clients of a scratch pool, and their open authorizations
+    pub(crate) fn commit(&mut self, scratch: ClientPool) {
+        self.authorizations
+            .retain(|(_, _, client_id)| !scratch.clients.contains_key(client_id));
+        self.authorizations.extend(scratch.authorizations);
+        self.clients.extend(scratch.clients);
+    }
+
+    // clients are returned in ClientId order
+    pub fn iter(&self) -> impl Iterator<Item = &Client> {
+        self.clients.values()
+    }
+
+    // A `pending` column is added when some client still has authorized
+    // funds, otherwise the total wouldn't add up from the other columns
     pub fn format_for_print(&self) -> Result<String> {
+        if self
+            .clients
+            .values()
+            .any(|client| !client.pending.is_zero())
+        {
+            let mut output = String::from("client, available, held, pending, total, locked\n");
+            for client in self.clients.values() {
+                writeln!(
+                    &mut output,
+                    "{}, {1:.4}, {2:.4}, {3:.4}, {4:.4}, {5}",
+                    client.id,
+                    client.available,
+                    client.held,
+                    client.pending,
+                    client.total,
+                    client.locked
+                )?;
+            }
+            return Ok(output);
+        }
+
         let mut output = String::from("client, available, held, total, locked\n");
         for (_, client) in self.clients.iter() {
             writeln!(
@@ -121,4 +575,38 @@ impl ClientPool {
         }
         Ok(output)
     }
+
+    // The same state as a pretty JSON array, with the profile of each client
+    // that has one
+    pub fn write_json<W: io::Write>(&self, writer: W) -> Result<()> {
+        let accounts: Vec<Account> = self
+            .clients
+            .values()
+            .map(|client| Account {
+                client_id: client.id,
+                available: to_4dp(client.available),
+                held: to_4dp(client.held),
+                pending: to_4dp(client.pending),
+                total: to_4dp(client.total),
+                locked: client.locked,
+                profile: self.profiles.get(&client.id),
+            })
+            .collect();
+        serde_json::to_writer_pretty(writer, &accounts)?;
+        Ok(())
+    }
+}
+
+// One client in the JSON account output
+#[derive(Serialize)]
+struct Account<'a> {
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    available: Decimal,
+    held: Decimal,
+    pending: Decimal,
+    total: Decimal,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<&'a ClientProfile>,
 }
@@ -0,0 +1,160 @@
+use anyhow::Result;
+
+use crate::clients::ClientPool;
+use crate::observers::{notify, EngineObserver};
+use crate::outbox::Outbox;
+use crate::policy::EnginePolicy;
+use crate::risk::RiskEngine;
+use crate::transactions::management::TransactionTree;
+use crate::transactions::processing::{
+    apply_with_event, expire_authorizations, process_with_risk, Outcome, ProcessingEvent,
+    TransactionError,
+};
+use crate::transactions::storage::TransactionStore;
+use crate::transactions::Transaction;
+
+// Owns the clients, the transaction store and the policy, so transactions can
+// be fed in one at a time or in batches. The state of the risk rules is kept
+// between calls too.
+pub struct Engine<S = TransactionTree> {
+    clients: ClientPool,
+    store: S,
+    policy: EnginePolicy,
+    risk: RiskEngine,
+    observers: Vec<Box<dyn EngineObserver>>,
+    outbox: Option<Outbox>,
+}
+
+impl Engine<TransactionTree> {
+    pub fn new(policy: EnginePolicy) -> Self {
+        Self::with_state(ClientPool::new(), TransactionTree::new(), policy)
+    }
+}
+
+impl<S: TransactionStore> Engine<S> {
+    // Starts from existing clients and an existing store, for example a
+    // pool with client profiles or a disk store
+    pub fn with_state(clients: ClientPool, store: S, policy: EnginePolicy) -> Self {
+        let risk = RiskEngine::new(policy.risk_rules.clone());
+        Self {
+            clients,
+            store,
+            policy,
+            risk,
+            observers: Vec::new(),
+            outbox: None,
+        }
+    }
+
+    // Observers are told about every transaction, in the order they were added
+    pub fn add_observer<O: EngineObserver + 'static>(&mut self, observer: O) {
+        self.observers.push(Box::new(observer));
+    }
+
+    // Balance changes are written to the outbox as they are committed, before
+    // the client changes
+    pub fn set_outbox(&mut self, outbox: Outbox) {
+        self.outbox = Some(outbox);
+    }
+
+    pub fn outbox_mut(&mut self) -> Option<&mut Outbox> {
+        self.outbox.as_mut()
+    }
+
+    // Applies a single transaction. Rows aren't reordered here, the policy's
+    // reorder window only applies to `apply_batch`.
+    //
+    // Authorizations that expired before the transaction's timestamp are
+    // voided first, observers and the outbox see those voids before it.
+    pub fn apply(&mut self, transaction: Transaction) -> Result<Outcome, TransactionError> {
+        let expired = expire_authorizations(
+            transaction.timestamp,
+            &mut self.clients,
+            &mut self.store,
+            &self.policy,
+            self.outbox.as_mut(),
+        )?;
+        for event in &expired {
+            self.report(event);
+        }
+
+        let (event, outcome) = apply_with_event(
+            transaction,
+            None,
+            &mut self.clients,
+            &mut self.store,
+            &self.policy,
+            &mut self.risk,
+            self.outbox.as_mut(),
+        )?;
+        self.report(&event);
+        outcome
+    }
+
+    fn report(&mut self, event: &ProcessingEvent) {
+        for observer in self.observers.iter_mut() {
+            notify(observer.as_mut(), event);
+        }
+    }
+
+    // Applies every transaction, skipping the ones that are rejected. Only a
+    // failing store stops the batch.
+    pub fn apply_batch(&mut self, transactions: Vec<Transaction>) -> Result<()> {
+        self.apply_batch_with_events(transactions, |_| {})
+    }
+
+    // Same as `apply_batch`, but calls `on_event` for every transaction
+    pub fn apply_batch_with_events<F>(
+        &mut self,
+        transactions: Vec<Transaction>,
+        mut on_event: F,
+    ) -> Result<()>
+    where
+        F: FnMut(&ProcessingEvent),
+    {
+        let observers = &mut self.observers;
+        process_with_risk(
+            transactions,
+            &mut self.clients,
+            &mut self.store,
+            &self.policy,
+            &mut self.risk,
+            self.outbox.as_mut(),
+            |event| {
+                for observer in observers.iter_mut() {
+                    notify(observer.as_mut(), event);
+                }
+                on_event(event);
+                Ok(())
+            },
+        )
+    }
+
+    // Lets every observer flush its output, the first failure is returned
+    pub fn finish(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for observer in self.observers.iter_mut() {
+            let finished = observer.finish();
+            if result.is_ok() {
+                result = finished;
+            }
+        }
+        result
+    }
+
+    pub fn clients(&self) -> &ClientPool {
+        &self.clients
+    }
+
+    pub fn transactions(&self) -> &S {
+        &self.store
+    }
+
+    pub fn policy(&self) -> &EnginePolicy {
+        &self.policy
+    }
+
+    pub fn into_parts(self) -> (ClientPool, S) {
+        (self.clients, self.store)
+    }
+}
@@ -0,0 +1,376 @@
+use anyhow::Result;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::io;
+
+use crate::clients::ClientId;
+use crate::reconcile::ExpectedAccount;
+use crate::transactions::{TransactionId, TransactionType};
+
+// How a synthetic workload is made. The rates are the share of rows of each
+// kind, what is left over is split between deposits and withdrawals.
+#[derive(Debug, Clone)]
+pub struct GeneratorConfig {
+    pub seed: u64,
+    pub rows: usize,
+    pub clients: u16,
+    // the share of the deposits and withdrawals that are deposits
+    pub deposit_share: f64,
+    pub dispute_rate: f64,
+    pub resolve_rate: f64,
+    pub chargeback_rate: f64,
+    // rows that reuse the id of an earlier deposit or withdrawal, half of
+    // them exact replays and half of them conflicting
+    pub duplicate_rate: f64,
+    // rows the engine has to reject: missing or negative amounts and
+    // disputes of transactions that don't exist
+    pub malformed_rate: f64,
+    // amounts are picked up to this, with four decimal places
+    pub max_amount: Decimal,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            rows: 1000,
+            clients: 100,
+            deposit_share: 0.6,
+            dispute_rate: 0.02,
+            resolve_rate: 0.01,
+            chargeback_rate: 0.005,
+            duplicate_rate: 0.0,
+            malformed_rate: 0.0,
+            max_amount: Decimal::new(1000, 0),
+        }
+    }
+}
+
+impl GeneratorConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.clients == 0 {
+            return Err(anyhow::anyhow!("at least one client is needed"));
+        }
+        if !(0.0..=1.0).contains(&self.deposit_share) {
+            return Err(anyhow::anyhow!(
+                "the deposit share has to be between 0 and 1"
+            ));
+        }
+        let rates = [
+            self.dispute_rate,
+            self.resolve_rate,
+            self.chargeback_rate,
+            self.duplicate_rate,
+            self.malformed_rate,
+        ];
+        if rates.iter().any(|rate| *rate < 0.0) || rates.iter().sum::<f64>() > 1.0 {
+            return Err(anyhow::anyhow!(
+                "the rates can't be negative and can add up to at most 1"
+            ));
+        }
+        if self.max_amount < Decimal::new(1, 4) {
+            return Err(anyhow::anyhow!("the max amount has to be at least 0.0001"));
+        }
+        Ok(())
+    }
+}
+
+// A row of the generated file, in the input format of the engine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GeneratedRow {
+    pub tx_type: TransactionType,
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+    pub amount: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ModelClient {
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+// Tracks what the engine does with every row under the default policy, which
+// is what makes the expected account state
+#[derive(Debug, Default)]
+struct Model {
+    clients: BTreeMap<ClientId, ModelClient>,
+    // accepted deposits that were never disputed
+    disputable: Vec<GeneratedRow>,
+    // deposits that are in dispute
+    disputed: Vec<GeneratedRow>,
+    // accepted deposits and withdrawals, the ones duplicates are made from
+    accepted: Vec<GeneratedRow>,
+}
+
+impl Model {
+    fn transfer(&mut self, row: &GeneratedRow) {
+        // rejected deposits and withdrawals still create the client
+        let client = self.clients.entry(row.client_id).or_default();
+        let amount = match row.amount {
+            Some(amount) if amount >= Decimal::ZERO && !client.locked => amount,
+            _ => return,
+        };
+
+        if row.tx_type == TransactionType::Deposit {
+            client.available += amount;
+            client.total += amount;
+            self.disputable.push(*row);
+        } else if client.available >= amount {
+            client.available -= amount;
+            client.total -= amount;
+        } else {
+            return;
+        }
+        self.accepted.push(*row);
+    }
+
+    // Disputes the deposit at `index` of the disputable ones. A rejected
+    // dispute isn't tried again, so the deposit is taken out either way.
+    fn dispute(&mut self, index: usize) {
+        let deposit = self.disputable.swap_remove(index);
+        let amount = deposit.amount.unwrap_or_default();
+        let client = self.clients.entry(deposit.client_id).or_default();
+        if client.locked || client.available < amount {
+            return;
+        }
+        client.available -= amount;
+        client.held += amount;
+        self.disputed.push(deposit);
+    }
+
+    // Resolves or charges back the dispute at `index`
+    fn settle(&mut self, index: usize, tx_type: TransactionType) {
+        let deposit = self.disputed[index];
+        let amount = deposit.amount.unwrap_or_default();
+        let client = self.clients.entry(deposit.client_id).or_default();
+        // disputes of a locked account stay open
+        if client.locked {
+            return;
+        }
+        self.disputed.swap_remove(index);
+        client.held -= amount;
+        if tx_type == TransactionType::Resolve {
+            client.available += amount;
+        } else {
+            client.total -= amount;
+            client.locked = true;
+        }
+    }
+}
+
+// A generated workload, with the account state the engine should end up in
+#[derive(Debug)]
+pub struct Workload {
+    pub rows: Vec<GeneratedRow>,
+    pub expected: Vec<ExpectedAccount>,
+}
+
+impl Workload {
+    pub fn write_csv<W: io::Write>(&self, writer: W) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["type", "client", "tx", "amount"])?;
+        for row in &self.rows {
+            writer.write_record([
+                row.tx_type.to_string(),
+                row.client_id.to_string(),
+                u32::from(row.tx_id).to_string(),
+                row.amount
+                    .map(|amount| amount.to_string())
+                    .unwrap_or_default(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    // The same format as the engine's output, so it can be passed to `--expected`
+    pub fn write_expected<W: io::Write>(&self, mut writer: W) -> Result<()> {
+        writeln!(writer, "client, available, held, total, locked")?;
+        for account in &self.expected {
+            writeln!(
+                writer,
+                "{}, {:.4}, {:.4}, {:.4}, {}",
+                account.client_id, account.available, account.held, account.total, account.locked
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct Generator {
+    config: GeneratorConfig,
+    rng: ChaCha8Rng,
+    model: Model,
+    next_tx_id: u32,
+    // the max amount in units of 0.0001
+    max_units: i64,
+}
+
+impl Generator {
+    fn amount(&mut self) -> Decimal {
+        Decimal::new(self.rng.gen_range(1..=self.max_units), 4)
+    }
+
+    fn client_id(&mut self) -> ClientId {
+        ClientId::from(self.rng.gen_range(1..=self.config.clients))
+    }
+
+    fn tx_id(&mut self) -> Result<TransactionId> {
+        let tx_id = TransactionId::from(self.next_tx_id);
+        self.next_tx_id = self
+            .next_tx_id
+            .checked_add(1)
+            .ok_or_else(|| anyhow::anyhow!("ran out of transaction ids"))?;
+        Ok(tx_id)
+    }
+
+    fn transfer(&mut self) -> Result<GeneratedRow> {
+        let tx_type = if self.rng.gen_bool(self.config.deposit_share) {
+            TransactionType::Deposit
+        } else {
+            TransactionType::Withdrawal
+        };
+        let row = GeneratedRow {
+            tx_type,
+            client_id: self.client_id(),
+            tx_id: self.tx_id()?,
+            amount: Some(self.amount()),
+        };
+        self.model.transfer(&row);
+        Ok(row)
+    }
+
+    fn dispute(&mut self) -> Option<GeneratedRow> {
+        if self.model.disputable.is_empty() {
+            return None;
+        }
+        let index = self.rng.gen_range(0..self.model.disputable.len());
+        let deposit = self.model.disputable[index];
+        self.model.dispute(index);
+        Some(referencing(TransactionType::Dispute, &deposit))
+    }
+
+    fn settle(&mut self, tx_type: TransactionType) -> Option<GeneratedRow> {
+        if self.model.disputed.is_empty() {
+            return None;
+        }
+        let index = self.rng.gen_range(0..self.model.disputed.len());
+        let deposit = self.model.disputed[index];
+        self.model.settle(index, tx_type);
+        Some(referencing(tx_type, &deposit))
+    }
+
+    // Replays and conflicting duplicates don't change anything
+    fn duplicate(&mut self) -> Option<GeneratedRow> {
+        let mut row = self.model.accepted.choose(&mut self.rng).copied()?;
+        if self.rng.gen_bool(0.5) {
+            // the same id for a different amount, which is rejected as a conflict
+            row.amount = row.amount.map(|amount| amount + Decimal::new(1, 4));
+        }
+        Some(row)
+    }
+
+    fn malformed(&mut self) -> Result<GeneratedRow> {
+        let mut row = GeneratedRow {
+            tx_type: TransactionType::Deposit,
+            client_id: self.client_id(),
+            tx_id: self.tx_id()?,
+            amount: None,
+        };
+        match self.rng.gen_range(0..3) {
+            0 => {}
+            1 => row.amount = Some(-self.amount()),
+            _ => {
+                // ids from the top down are never used by the other rows
+                row.tx_type = TransactionType::Dispute;
+                row.tx_id = TransactionId::from(u32::MAX - u32::from(row.tx_id));
+            }
+        }
+        if row.tx_type == TransactionType::Deposit {
+            self.model.transfer(&row);
+        }
+        Ok(row)
+    }
+
+    fn row(&mut self) -> Result<GeneratedRow> {
+        let mut roll = self.rng.gen::<f64>();
+        let mut pick = |rate: f64| {
+            roll -= rate;
+            roll < 0.0
+        };
+
+        let picked = if pick(self.config.malformed_rate) {
+            return self.malformed();
+        } else if pick(self.config.duplicate_rate) {
+            self.duplicate()
+        } else if pick(self.config.dispute_rate) {
+            self.dispute()
+        } else if pick(self.config.resolve_rate) {
+            self.settle(TransactionType::Resolve)
+        } else if pick(self.config.chargeback_rate) {
+            self.settle(TransactionType::Chargeback)
+        } else {
+            None
+        };
+
+        // when there is nothing to dispute or duplicate yet it is a plain transfer
+        match picked {
+            Some(row) => Ok(row),
+            None => self.transfer(),
+        }
+    }
+}
+
+fn referencing(tx_type: TransactionType, deposit: &GeneratedRow) -> GeneratedRow {
+    GeneratedRow {
+        tx_type,
+        client_id: deposit.client_id,
+        tx_id: deposit.tx_id,
+        amount: None,
+    }
+}
+
+// Generates a workload from the config, the same config always gives the same rows
+pub fn generate(config: &GeneratorConfig) -> Result<Workload> {
+    config.validate()?;
+
+    let mut generator = Generator {
+        config: config.clone(),
+        rng: ChaCha8Rng::seed_from_u64(config.seed),
+        model: Model::default(),
+        next_tx_id: 1,
+        max_units: (config.max_amount * Decimal::new(10_000, 0))
+            .trunc()
+            .to_i64()
+            .unwrap_or(i64::MAX),
+    };
+
+    let mut rows = Vec::with_capacity(config.rows);
+    for _ in 0..config.rows {
+        rows.push(generator.row()?);
+    }
+
+    let expected = generator
+        .model
+        .clients
+        .iter()
+        .map(|(client_id, client)| ExpectedAccount {
+            client_id: *client_id,
+            available: client.available,
+            held: client.held,
+            // the generator doesn't authorize, nothing is ever pending
+            pending: Decimal::ZERO,
+            total: client.total,
+            locked: client.locked,
+        })
+        .collect();
+
+    Ok(Workload { rows, expected })
+}
@@ -1,10 +1,30 @@
 use anyhow::Result;
 use std::io;
 
+pub mod audit;
 pub mod clients;
+pub mod engine;
+pub mod generator;
+pub mod metrics;
+pub mod observers;
+pub mod outbox;
+pub mod policy;
+pub mod receipts;
+pub mod reconcile;
+pub mod risk;
+pub mod statements;
 pub mod transactions;
 
 pub fn read_buffer_to_csv(filename: &str) -> Result<Vec<transactions::Transaction>> {
+    read_transactions(filename, clients::ClientIdKind::default())
+}
+
+// Same as `read_buffer_to_csv`, but client ids have to fit `client_ids`
+// rather than a u16
+pub fn read_transactions(
+    filename: &str,
+    client_ids: clients::ClientIdKind,
+) -> Result<Vec<transactions::Transaction>> {
     // Creating a BufReader to read the file will help
     // on memory usage and performance for large files.
     let file = std::fs::File::open(filename)?;
@@ -17,9 +37,15 @@ pub fn read_buffer_to_csv(filename: &str) -> Result<Vec<transactions::Transactio
 
     let mut trans = Vec::new();
 
-    for result in reader.deserialize() {
-        let record: transactions::Transaction = result?;
-        trans.push(record);
+    let headers = reader.headers()?.clone();
+    let mut record = csv::StringRecord::new();
+    while reader.read_record(&mut record)? {
+        let transaction: transactions::Transaction = record.deserialize(Some(&headers))?;
+        if let Err(e) = client_ids.check(transaction.client_id) {
+            let line = record.position().map_or(0, |position| position.line());
+            return Err(anyhow::anyhow!("{} on line {}", e, line));
+        }
+        trans.push(transaction);
     }
 
     Ok(trans)
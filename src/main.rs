@@ -1,46 +1,471 @@
+use std::collections::BTreeMap;
 use std::process;
 
-use tps::{clients, read_buffer_to_csv, transactions};
+use tps::engine::Engine;
+use tps::observers::{NdjsonObserver, StderrObserver};
+use tps::outbox::Outbox;
+use tps::policy::{EnginePolicy, SECONDS_PER_DAY};
+use tps::receipts::{Receipt, ReceiptWriter};
+use tps::transactions::management::{Retention, TransactionTree};
+use tps::transactions::processing::ProcessingEvent;
+use tps::transactions::storage::{DiskStore, TransactionStore};
+use tps::transactions::Transaction;
+use tps::{audit, clients, metrics, read_transactions, reconcile, risk, statements};
+use tracing_subscriber::EnvFilter;
+
+const USAGE: &str = "incorrect usage of the interface, please provide options in the format 'cargo run -- <input_file.csv> [--config <policy.toml>] [--expected <expected_accounts.csv>] [--statements <statements.csv|statements.json>] [--statement-client <client_id>] [--receipts <receipts.csv|receipts.json>] [--retention <full|compact>] [--store <directory>] [--dispute-window-days <days>] [--chargeback-window-days <days>] [--authorization-expiry-days <days>] [--reorder-window <rows>] [--first-seq <seq>] [--reserve-failed-ids] [--client-ids <u16|u64|string>] [--risk-rules <rules.csv>] [--client-profiles <profiles.csv>] [--output-format <csv|json>] [--metrics <metrics.prom>] [--observe <stderr|events.ndjson>] [--outbox <directory>] [--audit] [--log-level <filter>] [--log-format <text|json>]'";
+
+struct Args {
+    input_csv_filename: String,
+    expected_filename: Option<String>,
+    statements_filename: Option<String>,
+    statement_client: Option<clients::ClientId>,
+    receipts_filename: Option<String>,
+    retention: Retention,
+    store_dir: Option<String>,
+    risk_rules_filename: Option<String>,
+    profiles_filename: Option<String>,
+    // the account state on stdout as JSON instead of CSV
+    json_output: bool,
+    metrics_filename: Option<String>,
+    // `stderr` or an NDJSON file, for each observer to register
+    observers: Vec<String>,
+    outbox_dir: Option<String>,
+    audit: bool,
+    policy: EnginePolicy,
+}
+
+// Other options override what the config file sets, so the policy from the
+// config file is read first and passed in
+fn parse_args(args: &[String], mut policy: EnginePolicy) -> Option<Args> {
+    let mut input_csv_filename = None;
+    let mut expected_filename = None;
+    let mut statements_filename = None;
+    let mut statement_client = None;
+    let mut receipts_filename = None;
+    let mut retention = Retention::Full;
+    let mut store_dir = None;
+    let mut risk_rules_filename = None;
+    let mut profiles_filename = None;
+    let mut json_output = false;
+    let mut metrics_filename = None;
+    let mut observers = Vec::new();
+    let mut outbox_dir = None;
+    let mut audit = false;
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            // read before the rest of the options
+            "--config" | "--log-level" | "--log-format" => {
+                iter.next()?;
+            }
+            "--expected" => expected_filename = Some(iter.next()?.clone()),
+            "--statements" => statements_filename = Some(iter.next()?.clone()),
+            "--statement-client" => statement_client = Some(iter.next()?.parse().ok()?),
+            "--receipts" => receipts_filename = Some(iter.next()?.clone()),
+            "--store" => store_dir = Some(iter.next()?.clone()),
+            "--dispute-window-days" => {
+                let days: u64 = iter.next()?.parse().ok()?;
+                policy.dispute_window_secs = Some(days.checked_mul(SECONDS_PER_DAY)?);
+            }
+            "--chargeback-window-days" => {
+                let days: u64 = iter.next()?.parse().ok()?;
+                policy.chargeback_window_secs = Some(days.checked_mul(SECONDS_PER_DAY)?);
+            }
+            "--authorization-expiry-days" => {
+                let days: u64 = iter.next()?.parse().ok()?;
+                policy.authorization_expiry_secs = Some(days.checked_mul(SECONDS_PER_DAY)?);
+            }
+            "--reorder-window" => policy.reorder_window = Some(iter.next()?.parse().ok()?),
+            "--first-seq" => policy.first_seq = iter.next()?.parse().ok()?,
+            "--reserve-failed-ids" => policy.reserve_failed_ids = true,
+            "--client-ids" => policy.client_ids = iter.next()?.parse().ok()?,
+            "--risk-rules" => risk_rules_filename = Some(iter.next()?.clone()),
+            "--client-profiles" => profiles_filename = Some(iter.next()?.clone()),
+            "--output-format" => {
+                json_output = match iter.next()?.as_str() {
+                    "csv" => false,
+                    "json" => true,
+                    _ => return None,
+                }
+            }
+            "--metrics" => metrics_filename = Some(iter.next()?.clone()),
+            "--observe" => observers.push(iter.next()?.clone()),
+            "--outbox" => outbox_dir = Some(iter.next()?.clone()),
+            "--audit" => audit = true,
+            "--retention" => {
+                retention = match iter.next()?.as_str() {
+                    "full" => Retention::Full,
+                    "compact" => Retention::Compact,
+                    _ => return None,
+                }
+            }
+            _ if input_csv_filename.is_none() && !arg.starts_with("--") => {
+                input_csv_filename = Some(arg.clone())
+            }
+            _ => return None,
+        }
+    }
+
+    Some(Args {
+        input_csv_filename: input_csv_filename?,
+        expected_filename,
+        statements_filename,
+        statement_client,
+        receipts_filename,
+        retention,
+        store_dir,
+        risk_rules_filename,
+        profiles_filename,
+        json_output,
+        metrics_filename,
+        observers,
+        outbox_dir,
+        audit,
+        policy,
+    })
+}
+
+// The value of an option that has to be known before the rest are parsed
+fn early_option<'a>(args: &'a [String], name: &str) -> anyhow::Result<Option<&'a str>> {
+    match args.iter().position(|arg| arg == name) {
+        Some(index) => match args.get(index + 1) {
+            Some(value) => Ok(Some(value.as_str())),
+            None => Err(anyhow::anyhow!("{} needs a value", name)),
+        },
+        None => Ok(None),
+    }
+}
+
+fn read_config(args: &[String]) -> anyhow::Result<EnginePolicy> {
+    match early_option(args, "--config")? {
+        Some(config_filename) => EnginePolicy::from_toml_file(config_filename),
+        None => Ok(EnginePolicy::default()),
+    }
+}
+
+// Logs go to stderr so stdout stays the account state. The level is an
+// `EnvFilter` directive like `debug` or `tps=debug`, `info` by default.
+fn init_logging(args: &[String]) -> anyhow::Result<()> {
+    let filter = EnvFilter::try_new(early_option(args, "--log-level")?.unwrap_or("info"))?;
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+
+    match early_option(args, "--log-format")? {
+        None | Some("text") => builder.init(),
+        Some("json") => builder.json().init(),
+        Some(other) => return Err(anyhow::anyhow!("unknown log format '{}'", other)),
+    }
+    Ok(())
+}
+
+// The statement format is picked from the file extension, CSV unless it is `.json`
+fn write_statements(
+    builder: &statements::StatementBuilder,
+    statements_filename: &str,
+) -> anyhow::Result<()> {
+    let file = std::io::BufWriter::new(std::fs::File::create(statements_filename)?);
+    if statements_filename.ends_with(".json") {
+        builder.write_json(file)
+    } else {
+        builder.write_csv(file)
+    }
+}
+
+// Names what the outbox is written for: the input file, the client profiles
+// and the policy, as an FNV-1a hash. Any of them changes the events. The
+// transaction store doesn't, `--store` only ever starts from an empty one.
+fn input_fingerprint(args: &Args) -> anyhow::Result<String> {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut add = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    };
+    add(&std::fs::read(&args.input_csv_filename)?);
+    if let Some(profiles_filename) = &args.profiles_filename {
+        add(&std::fs::read(profiles_filename)?);
+    }
+    add(format!("{:?}", args.policy).as_bytes());
+    Ok(format!("{:016x}", hash))
+}
+
+// Runs the whole file through the engine with its observers and outbox, and
+// hands back the state for printing and auditing
+fn run_engine<S, F>(
+    mut engine: Engine<S>,
+    transactions: Vec<Transaction>,
+    observers: &[String],
+    outbox: Option<Outbox>,
+    on_event: F,
+) -> anyhow::Result<(clients::ClientPool, S)>
+where
+    S: TransactionStore,
+    F: FnMut(&ProcessingEvent),
+{
+    for observer in observers {
+        match observer.as_str() {
+            "stderr" => engine.add_observer(StderrObserver),
+            filename => engine.add_observer(NdjsonObserver::new(std::io::BufWriter::new(
+                std::fs::File::create(filename)?,
+            ))),
+        }
+    }
+    if let Some(outbox) = outbox {
+        engine.set_outbox(outbox);
+    }
+    engine.apply_batch_with_events(transactions, on_event)?;
+    engine.finish()?;
+    // what the consumer has acknowledged so far isn't needed any more
+    if let Some(outbox) = engine.outbox_mut() {
+        let compacted = outbox.compact()?;
+        tracing::info!(written = outbox.written(), compacted, "outbox written");
+    }
+    Ok(engine.into_parts())
+}
+
+// Receipts are JSON lines for a `.json` file and CSV otherwise, like statements
+fn open_receipts(
+    receipts_filename: &str,
+) -> anyhow::Result<ReceiptWriter<std::io::BufWriter<std::fs::File>>> {
+    let file = std::io::BufWriter::new(std::fs::File::create(receipts_filename)?);
+    if receipts_filename.ends_with(".json") {
+        Ok(ReceiptWriter::json(file))
+    } else {
+        Ok(ReceiptWriter::csv(file))
+    }
+}
 
 fn main() {
     let args_vec: Vec<String> = std::env::args().collect();
 
-    if args_vec.len() != 2 {
-        eprintln!("incorrect usage of the interface, please provide options in the format 'cargo run -- <input_file.csv>'"
-        );
+    // nothing can be logged until this is done
+    if let Err(e) = init_logging(&args_vec) {
+        eprintln!("could not set up logging due to: {}", e);
+        process::exit(1);
+    }
+
+    let policy = match read_config(&args_vec) {
+        Ok(policy) => policy,
+        Err(e) => {
+            tracing::error!(error = %e, "could not read config");
+            process::exit(1);
+        }
+    };
+
+    let mut args = match parse_args(&args_vec, policy) {
+        Some(args) => args,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+
+    // The open disputes of a disk store can't be listed, so only the
+    // in-memory tree can be audited
+    if args.audit && args.store_dir.is_some() {
+        tracing::error!("the audit needs the in-memory transaction store, not --store");
+        process::exit(1);
+    }
+
+    // disk store records only have room for numeric client ids
+    if args.store_dir.is_some() && args.policy.client_ids == clients::ClientIdKind::String {
+        tracing::error!("--store only supports u16 and u64 client ids");
         process::exit(1);
     }
 
-    let input_csv_filename = &args_vec[1];
+    if let Some(risk_rules_filename) = &args.risk_rules_filename {
+        match risk::read_risk_rules(risk_rules_filename) {
+            Ok(rules) => args.policy.risk_rules = rules,
+            Err(e) => {
+                tracing::error!(error = %e, "could not read risk rules");
+                process::exit(1);
+            }
+        }
+    }
 
     // We can use the file buffer to read the CSV file into a vector of transactions.
-    let csv_content = match read_buffer_to_csv(input_csv_filename) {
+    let csv_content = match read_transactions(&args.input_csv_filename, args.policy.client_ids) {
         Ok(content) => content,
         Err(e) => {
-            eprintln!(
-                "could not read csv contents from created file buffer due to: {}",
-                e
-            );
+            tracing::error!(error = %e, "could not read csv contents from created file buffer");
             process::exit(1);
         }
     };
 
+    // an outbox left by a different input or policy is refused
+    let outbox = match args.outbox_dir.as_deref().map(|outbox_dir| {
+        input_fingerprint(&args).and_then(|input| Outbox::open(outbox_dir, &input))
+    }) {
+        Some(Ok(outbox)) => Some(outbox),
+        Some(Err(e)) => {
+            tracing::error!(error = %e, "could not open outbox");
+            process::exit(1);
+        }
+        None => None,
+    };
+
     // create client pool to have transactions operate on
     // create transaction record
     // we want these to outlive the processing in case we need to store it
-    let mut client_pool = clients::ClientPool::new();
-    let mut transations = transactions::management::TransactionTree::new();
+    let profiles = match &args.profiles_filename {
+        Some(profiles_filename) => match clients::read_client_profiles(profiles_filename) {
+            Ok(profiles) => profiles,
+            Err(e) => {
+                tracing::error!(error = %e, "could not read client profiles");
+                process::exit(1);
+            }
+        },
+        None => BTreeMap::new(),
+    };
+    let client_pool = clients::ClientPool::with_profiles(profiles.clone());
 
-    //process the transactions
-    transactions::processing::process_transactions(csv_content, &mut client_pool, &mut transations)
-        .unwrap();
+    // statements are only collected when they were asked for
+    let mut statement_builder = args
+        .statements_filename
+        .as_ref()
+        .map(|_| statements::StatementBuilder::new(args.statement_client).with_profiles(profiles));
+    // hits per risk rule and action, for the summary at the end
+    let mut rule_hits: BTreeMap<(String, risk::RuleAction), usize> = BTreeMap::new();
+    // receipts are written as the transactions are applied
+    let mut receipt_writer = match args.receipts_filename.as_deref().map(open_receipts) {
+        Some(Ok(writer)) => Some(writer),
+        Some(Err(e)) => {
+            tracing::error!(error = %e, "could not create receipts file");
+            process::exit(1);
+        }
+        None => None,
+    };
+    let mut receipt_error = None;
+    let mut run_metrics = metrics::Metrics::new();
+    let mut ledger = args.audit.then(audit::Ledger::new);
+    let on_event = |event: &ProcessingEvent| {
+        run_metrics.record(event);
+        if let Some(ledger) = ledger.as_mut() {
+            ledger.record(event);
+        }
+        if let Some(builder) = statement_builder.as_mut() {
+            builder.record(event);
+        }
+        for hit in &event.rule_hits {
+            *rule_hits.entry((hit.rule.clone(), hit.action)).or_default() += 1;
+        }
+        // after the first failure no more receipts are written
+        if let (Some(writer), Some(receipt), None) = (
+            receipt_writer.as_mut(),
+            Receipt::from_event(event),
+            &receipt_error,
+        ) {
+            receipt_error = writer.write(&receipt).err();
+        }
+    };
 
-    // This prints out to stdout to allow the desired output behaviour
-    match client_pool.format_for_print() {
-        Ok(client_str) => (println!("{client_str}")),
+    //process the transactions, on disk if a store directory was given
+    let file_span = tracing::info_span!("process_file", file = %args.input_csv_filename).entered();
+    let processed = match &args.store_dir {
+        Some(store_dir) => DiskStore::open(store_dir)
+            .and_then(|store| {
+                // the clients and authorizations of an earlier run aren't
+                // kept, its rows would only come back as replays
+                if !store.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "transaction store in {} already has transactions from an earlier run, start from an empty directory",
+                        store_dir
+                    ));
+                }
+                let engine = Engine::with_state(client_pool, store, args.policy.clone());
+                run_engine(engine, csv_content, &args.observers, outbox, on_event)
+            })
+            .map(|(client_pool, _)| (client_pool, None)),
+        None => {
+            let store = TransactionTree::with_retention(args.retention);
+            let engine = Engine::with_state(client_pool, store, args.policy.clone());
+            run_engine(engine, csv_content, &args.observers, outbox, on_event)
+                .map(|(client_pool, transactions)| (client_pool, Some(transactions)))
+        }
+    };
+
+    let (client_pool, transactions) = match processed {
+        Ok(processed) => processed,
         Err(e) => {
-            eprintln!("could not print final client state due to: {}", e);
+            tracing::error!(error = %e, "could not process transactions");
             process::exit(1);
         }
     };
+
+    if let Some(writer) = receipt_writer.as_mut() {
+        if let Some(e) = receipt_error.or_else(|| writer.flush().err()) {
+            tracing::error!(error = %e, "could not write receipts");
+            process::exit(1);
+        }
+    }
+
+    for ((rule, action), count) in &rule_hits {
+        tracing::info!(rule = %rule, %action, count, "risk rule hits");
+    }
+    file_span.exit();
+
+    if let Some(metrics_filename) = &args.metrics_filename {
+        run_metrics.observe_clients(&client_pool);
+        if let Err(e) = run_metrics.write_textfile(metrics_filename) {
+            tracing::error!(error = %e, "could not write metrics");
+            process::exit(1);
+        }
+    }
+
+    if let (Some(builder), Some(statements_filename)) =
+        (&statement_builder, &args.statements_filename)
+    {
+        if let Err(e) = write_statements(builder, statements_filename) {
+            tracing::error!(error = %e, "could not write statements");
+            process::exit(1);
+        }
+    }
+
+    // This prints out to stdout to allow the desired output behaviour
+    let printed = if args.json_output {
+        client_pool
+            .write_json(std::io::stdout().lock())
+            .map(|_| println!())
+    } else {
+        client_pool
+            .format_for_print()
+            .map(|client_str| println!("{client_str}"))
+    };
+    if let Err(e) = printed {
+        tracing::error!(error = %e, "could not print final client state");
+        process::exit(1);
+    }
+
+    // The reconciliation report goes to stderr so stdout stays the account state
+    if let Some(expected_filename) = &args.expected_filename {
+        match reconcile::reconcile_file(expected_filename, &client_pool) {
+            Ok(report) => {
+                eprint!("{}", report);
+                if !report.is_clean() {
+                    process::exit(1);
+                }
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "could not reconcile against expected accounts");
+                process::exit(1);
+            }
+        }
+    }
+
+    // the audit was refused up front for a disk store, so the tree is there
+    if let (Some(ledger), Some(transactions)) = (&ledger, &transactions) {
+        let report = audit::audit(ledger, &client_pool, transactions);
+        eprint!("{}", report);
+        if !report.is_clean() {
+            tracing::error!(
+                discrepancies = report.discrepancies.len(),
+                "ledger audit failed"
+            );
+            process::exit(1);
+        }
+    }
 }
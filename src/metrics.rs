@@ -0,0 +1,198 @@
+use anyhow::Result;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::path::Path;
+
+use crate::clients::ClientPool;
+use crate::transactions::processing::ProcessingEvent;
+use crate::transactions::TransactionType;
+
+// Upper bounds of the apply latency buckets, in seconds
+const LATENCY_BUCKETS: [f64; 8] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.01,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Outcome {
+    Accepted,
+    Rejected,
+    // an exact replay that was acknowledged without being applied
+    Replay,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Accepted => write!(f, "accepted"),
+            Outcome::Rejected => write!(f, "rejected"),
+            Outcome::Replay => write!(f, "replay"),
+        }
+    }
+}
+
+impl Outcome {
+    pub fn of(event: &ProcessingEvent) -> Self {
+        if event.replay {
+            Outcome::Replay
+        } else if event.is_accepted() {
+            Outcome::Accepted
+        } else {
+            Outcome::Rejected
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    // counts per bucket, not cumulative, the last one is +Inf
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+// Counters and gauges for a processing run, collected from processing
+// events and rendered in the Prometheus text format
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    transactions: BTreeMap<(TransactionType, Outcome), u64>,
+    apply_latency: Histogram,
+    clients: usize,
+    locked_accounts: usize,
+    held_funds: Decimal,
+    pending_funds: Decimal,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, event: &ProcessingEvent) {
+        *self
+            .transactions
+            .entry((event.transaction.tx_type, Outcome::of(event)))
+            .or_default() += 1;
+        self.apply_latency.observe(event.elapsed.as_secs_f64());
+    }
+
+    // Updates the gauges from the current state of the clients
+    pub fn observe_clients(&mut self, clients: &ClientPool) {
+        self.clients = 0;
+        self.locked_accounts = 0;
+        self.held_funds = Decimal::ZERO;
+        self.pending_funds = Decimal::ZERO;
+
+        for client in clients.iter() {
+            self.clients += 1;
+            if client.locked {
+                self.locked_accounts += 1;
+            }
+            self.held_funds += client.held;
+            self.pending_funds += client.pending;
+        }
+    }
+
+    pub fn transactions(&self, tx_type: TransactionType, outcome: Outcome) -> u64 {
+        self.transactions
+            .get(&(tx_type, outcome))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn apply_latency_count(&self) -> u64 {
+        self.apply_latency.count
+    }
+
+    pub fn render(&self) -> Result<String> {
+        let mut output = String::new();
+
+        writeln!(
+            output,
+            "# HELP tps_transactions_total Processed transactions by type and outcome."
+        )?;
+        writeln!(output, "# TYPE tps_transactions_total counter")?;
+        for ((tx_type, outcome), count) in &self.transactions {
+            writeln!(
+                output,
+                "tps_transactions_total{{type=\"{}\",outcome=\"{}\"}} {}",
+                tx_type, outcome, count
+            )?;
+        }
+
+        writeln!(output, "# HELP tps_clients Clients in the pool.")?;
+        writeln!(output, "# TYPE tps_clients gauge")?;
+        writeln!(output, "tps_clients {}", self.clients)?;
+
+        writeln!(output, "# HELP tps_locked_accounts Locked client accounts.")?;
+        writeln!(output, "# TYPE tps_locked_accounts gauge")?;
+        writeln!(output, "tps_locked_accounts {}", self.locked_accounts)?;
+
+        writeln!(output, "# HELP tps_held_funds Funds held by open disputes.")?;
+        writeln!(output, "# TYPE tps_held_funds gauge")?;
+        writeln!(output, "tps_held_funds {}", self.held_funds)?;
+
+        writeln!(
+            output,
+            "# HELP tps_pending_funds Funds authorized but not yet captured or voided."
+        )?;
+        writeln!(output, "# TYPE tps_pending_funds gauge")?;
+        writeln!(output, "tps_pending_funds {}", self.pending_funds)?;
+
+        writeln!(
+            output,
+            "# HELP tps_apply_duration_seconds Time taken to apply a single transaction."
+        )?;
+        writeln!(output, "# TYPE tps_apply_duration_seconds histogram")?;
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&self.apply_latency.buckets) {
+            cumulative += count;
+            writeln!(
+                output,
+                "tps_apply_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, cumulative
+            )?;
+        }
+        writeln!(
+            output,
+            "tps_apply_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            self.apply_latency.count
+        )?;
+        writeln!(
+            output,
+            "tps_apply_duration_seconds_sum {}",
+            self.apply_latency.sum
+        )?;
+        writeln!(
+            output,
+            "tps_apply_duration_seconds_count {}",
+            self.apply_latency.count
+        )?;
+
+        Ok(output)
+    }
+
+    // Writes the metrics for a textfile collector. The file is written next
+    // to the target and renamed over it, so a scrape never sees half a file.
+    pub fn write_textfile<P: AsRef<Path>>(&self, filename: P) -> Result<()> {
+        let filename = filename.as_ref();
+        let mut partial = filename.as_os_str().to_owned();
+        partial.push(".tmp");
+
+        std::fs::write(&partial, self.render()?)?;
+        std::fs::rename(&partial, filename)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::Result;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::io;
+
+use crate::clients::ClientId;
+use crate::transactions::processing::ProcessingEvent;
+use crate::transactions::{to_4dp, TransactionId, TransactionType};
+
+// Callbacks for what happens to transactions, for integrating with systems
+// downstream of the engine. Every callback does nothing by default, so an
+// observer only implements the ones it cares about.
+//
+// A transaction can trigger more than one: an accepted chargeback calls
+// `on_accepted`, `on_dispute_closed`, `on_chargeback` and then
+// `on_account_locked`.
+pub trait EngineObserver {
+    fn on_accepted(&mut self, _event: &ProcessingEvent) {}
+    fn on_rejected(&mut self, _event: &ProcessingEvent) {}
+    // an exact replay that was acknowledged without being applied
+    fn on_replay(&mut self, _event: &ProcessingEvent) {}
+    fn on_dispute_opened(&mut self, _event: &ProcessingEvent) {}
+    // by a resolve or a chargeback
+    fn on_dispute_closed(&mut self, _event: &ProcessingEvent) {}
+    fn on_chargeback(&mut self, _event: &ProcessingEvent) {}
+    fn on_account_locked(&mut self, _event: &ProcessingEvent) {}
+
+    // Called once processing is done, for observers that buffer their
+    // output or need to report a failure
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Calls the observer's callbacks that apply to the event
+pub fn notify(observer: &mut dyn EngineObserver, event: &ProcessingEvent) {
+    if event.replay {
+        observer.on_replay(event);
+        return;
+    }
+    if !event.is_accepted() {
+        observer.on_rejected(event);
+        return;
+    }
+
+    observer.on_accepted(event);
+    // disputes of transactions that aren't disputable move nothing
+    let moved = event.amount.is_some_and(|amount| amount != Decimal::ZERO);
+    match event.transaction.tx_type {
+        TransactionType::Dispute if moved => observer.on_dispute_opened(event),
+        TransactionType::Resolve if moved => observer.on_dispute_closed(event),
+        TransactionType::Chargeback if moved => {
+            observer.on_dispute_closed(event);
+            observer.on_chargeback(event);
+        }
+        _ => {}
+    }
+    if event.locked_account() {
+        observer.on_account_locked(event);
+    }
+}
+
+// Prints the events that need someone's attention to stderr: disputes,
+// chargebacks and locked accounts
+#[derive(Debug, Default)]
+pub struct StderrObserver;
+
+impl StderrObserver {
+    fn print(&self, what: &str, event: &ProcessingEvent) {
+        eprintln!(
+            "{}: client {}, tx {}, amount {:.4}",
+            what,
+            event.transaction.client_id,
+            u32::from(event.transaction.tx_id),
+            event.amount.unwrap_or_default()
+        );
+    }
+}
+
+impl EngineObserver for StderrObserver {
+    fn on_dispute_opened(&mut self, event: &ProcessingEvent) {
+        self.print("dispute opened", event);
+    }
+
+    fn on_dispute_closed(&mut self, event: &ProcessingEvent) {
+        self.print("dispute closed", event);
+    }
+
+    fn on_chargeback(&mut self, event: &ProcessingEvent) {
+        self.print("chargeback", event);
+    }
+
+    fn on_account_locked(&mut self, event: &ProcessingEvent) {
+        self.print("account locked", event);
+    }
+}
+
+// One line of an NDJSON observer file
+#[derive(Serialize, Debug)]
+struct ObservedEvent<'a> {
+    event: &'a str,
+    #[serde(rename = "tx")]
+    tx_id: TransactionId,
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+    amount: Option<Decimal>,
+    reason: Option<&'a str>,
+}
+
+// Writes every callback as a JSON object on its own line. A write failure
+// stops the output and is returned from `finish`.
+pub struct NdjsonObserver<W: io::Write> {
+    writer: W,
+    error: Option<anyhow::Error>,
+}
+
+impl<W: io::Write> NdjsonObserver<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            error: None,
+        }
+    }
+
+    fn write(&mut self, name: &str, event: &ProcessingEvent) {
+        if self.error.is_some() {
+            return;
+        }
+        let line = ObservedEvent {
+            event: name,
+            tx_id: event.transaction.tx_id,
+            client_id: event.transaction.client_id,
+            tx_type: event.transaction.tx_type,
+            amount: event.amount.or(event.transaction.amount).map(to_4dp),
+            reason: event.rejection.as_deref(),
+        };
+        let written = serde_json::to_writer(&mut self.writer, &line)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| writeln!(self.writer).map_err(anyhow::Error::from));
+        self.error = written.err();
+    }
+}
+
+impl<W: io::Write> EngineObserver for NdjsonObserver<W> {
+    fn on_accepted(&mut self, event: &ProcessingEvent) {
+        self.write("accepted", event);
+    }
+
+    fn on_rejected(&mut self, event: &ProcessingEvent) {
+        self.write("rejected", event);
+    }
+
+    fn on_replay(&mut self, event: &ProcessingEvent) {
+        self.write("replay", event);
+    }
+
+    fn on_dispute_opened(&mut self, event: &ProcessingEvent) {
+        self.write("dispute_opened", event);
+    }
+
+    fn on_dispute_closed(&mut self, event: &ProcessingEvent) {
+        self.write("dispute_closed", event);
+    }
+
+    fn on_chargeback(&mut self, event: &ProcessingEvent) {
+        self.write("chargeback", event);
+    }
+
+    fn on_account_locked(&mut self, event: &ProcessingEvent) {
+        self.write("account_locked", event);
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(self.writer.flush()?),
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use anyhow::Result;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::clients::ClientId;
+use crate::receipts::Receipt;
+use crate::transactions::processing::ProcessingEvent;
+use crate::transactions::storage::StoreError;
+use crate::transactions::{TransactionId, TransactionType};
+
+const LOG_FILE: &str = "outbox.log";
+const CURSOR_FILE: &str = "outbox.cursor";
+const INPUT_FILE: &str = "outbox.input";
+
+// A change to a client's balances, as downstream services see it. `seq`
+// numbers the balance changes of the input from one, so the same input
+// always gives the same events.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutboxEvent {
+    pub seq: u64,
+    #[serde(rename = "tx")]
+    pub tx_id: TransactionId,
+    #[serde(rename = "client", deserialize_with = "ClientId::deserialize_any")]
+    pub client_id: ClientId,
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    pub amount: Decimal,
+    // the client's balances after the change
+    pub available: Decimal,
+    pub held: Decimal,
+    pub pending: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl OutboxEvent {
+    // Only applied transactions that changed the client get an event,
+    // disputes of transactions that can't be disputed don't for example
+    pub fn from_event(seq: u64, event: &ProcessingEvent) -> Option<Self> {
+        let receipt = Receipt::from_event(event)?;
+        let locked = event.after.is_some_and(|client| client.locked);
+        Self::from_receipt(seq, &receipt, locked)
+    }
+
+    // The same from the receipt of an applied transaction, `locked` is
+    // whether the account is locked after it
+    pub fn from_receipt(seq: u64, receipt: &Receipt, locked: bool) -> Option<Self> {
+        let changed = receipt.available_before != receipt.available_after
+            || receipt.held_before != receipt.held_after
+            || receipt.pending_before != receipt.pending_after
+            || receipt.total_before != receipt.total_after
+            || receipt.locked;
+        if !changed {
+            return None;
+        }
+
+        Some(Self {
+            seq,
+            tx_id: receipt.tx_id,
+            client_id: receipt.client_id,
+            tx_type: receipt.tx_type,
+            amount: receipt.amount,
+            available: receipt.available_after,
+            held: receipt.held_after,
+            pending: receipt.pending_after,
+            total: receipt.total_after,
+            locked,
+        })
+    }
+}
+
+// Writes balance changes to `outbox.log` in a directory, one JSON object per
+// line. Every event is synced to disk before the transaction that made it is
+// reported as applied, so a change the caller has seen is never lost.
+//
+// The events of an input are numbered from one. Reopening the outbox and
+// processing the same input again, for example after a crash, skips the
+// events that are already in the log, so none are written twice. The outbox
+// remembers which input it was opened for in `outbox.input`, and refuses to
+// open for a different one, whose events would be skipped by mistake.
+#[derive(Debug)]
+pub struct Outbox {
+    dir: PathBuf,
+    log: File,
+    // the seq of the last event in the log, or of the last one compacted away
+    written: u64,
+    // the seq the next balance change gets
+    next_seq: u64,
+}
+
+impl Outbox {
+    // Opens the outbox in the directory for the input named by `input`,
+    // creating it if needed. `input` has to change whenever the events of the
+    // input would, a hash of the input and the policy for example. An event
+    // that was only partly written when the process stopped is dropped.
+    pub fn open<P: AsRef<Path>>(dir: P, input: &str) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        check_input(&dir, input)?;
+
+        let mut log = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(LOG_FILE))?;
+
+        let mut contents = Vec::new();
+        log.read_to_end(&mut contents)?;
+        let complete = contents
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |end| end + 1);
+        if complete < contents.len() {
+            tracing::warn!(
+                bytes = contents.len() - complete,
+                "dropping a partly written outbox event"
+            );
+            log.set_len(complete as u64)?;
+            log.sync_data()?;
+        }
+
+        let last = match contents[..complete]
+            .split(|byte| *byte == b'\n')
+            .rev()
+            .nth(1)
+        {
+            Some(line) => serde_json::from_slice::<OutboxEvent>(line)?.seq,
+            None => 0,
+        };
+
+        Ok(Self {
+            written: last.max(read_cursor(&dir)?),
+            dir,
+            log,
+            next_seq: 1,
+        })
+    }
+
+    // Records the balance change of an event, if it made one
+    pub fn record(&mut self, event: &ProcessingEvent) -> Result<(), StoreError> {
+        match Receipt::from_event(event) {
+            Some(receipt) => {
+                let locked = event.after.is_some_and(|client| client.locked);
+                self.record_receipt(&receipt, locked)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Records the balance change of an applied transaction, if it made one
+    pub fn record_receipt(&mut self, receipt: &Receipt, locked: bool) -> Result<(), StoreError> {
+        let Some(outbox_event) = OutboxEvent::from_receipt(self.next_seq, receipt, locked) else {
+            return Ok(());
+        };
+        self.next_seq += 1;
+
+        // written before the process stopped
+        if outbox_event.seq <= self.written {
+            return Ok(());
+        }
+
+        let mut line = serde_json::to_vec(&outbox_event).map_err(io::Error::from)?;
+        line.push(b'\n');
+        self.log.seek(SeekFrom::End(0))?;
+        self.log.write_all(&line)?;
+        self.log.sync_data()?;
+        self.written = outbox_event.seq;
+
+        Ok(())
+    }
+
+    // The seq of the last event written
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    // Drops the events the consumer has acknowledged from the log, returns
+    // how many were dropped
+    pub fn compact(&mut self) -> Result<usize> {
+        let cursor = read_cursor(&self.dir)?;
+        let events = read_events(&self.dir.join(LOG_FILE), 0, None)?;
+        let (acked, kept): (Vec<_>, Vec<_>) =
+            events.into_iter().partition(|event| event.seq <= cursor);
+        if acked.is_empty() {
+            return Ok(0);
+        }
+
+        // the new log replaces the old one in a single rename, a crash leaves
+        // one or the other
+        let tmp = self.dir.join(format!("{}.tmp", LOG_FILE));
+        {
+            let mut file = io::BufWriter::new(File::create(&tmp)?);
+            for event in &kept {
+                serde_json::to_writer(&mut file, event)?;
+                writeln!(file)?;
+            }
+            file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        }
+        std::fs::rename(&tmp, self.dir.join(LOG_FILE))?;
+        sync_dir(&self.dir)?;
+
+        self.log = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(self.dir.join(LOG_FILE))?;
+
+        Ok(acked.len())
+    }
+}
+
+// Reads the events in an outbox directory. Events are delivered at least
+// once: the ones after the acknowledged cursor come again after a restart.
+// A consumer that keeps the seq of the last event it handled together with
+// whatever it did with it gets every event exactly once by skipping the ones
+// it has seen.
+#[derive(Debug)]
+pub struct OutboxConsumer {
+    dir: PathBuf,
+    cursor: u64,
+    // the seq of the last event handed out by `poll`
+    delivered: u64,
+}
+
+impl OutboxConsumer {
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let cursor = read_cursor(&dir)?;
+        Ok(Self {
+            dir,
+            cursor,
+            delivered: cursor,
+        })
+    }
+
+    // Up to `max` events after the ones already handed out
+    pub fn poll(&mut self, max: usize) -> Result<Vec<OutboxEvent>> {
+        let events = read_events(&self.dir.join(LOG_FILE), self.delivered, Some(max))?;
+        if let Some(last) = events.last() {
+            self.delivered = last.seq;
+        }
+        Ok(events)
+    }
+
+    // Marks every event up to and including `seq` as handled. The cursor is
+    // on disk when this returns.
+    pub fn ack(&mut self, seq: u64) -> Result<()> {
+        if seq > self.delivered {
+            return Err(anyhow::anyhow!(
+                "can't acknowledge outbox event {}, only up to {} was delivered",
+                seq,
+                self.delivered
+            ));
+        }
+        if seq <= self.cursor {
+            return Ok(());
+        }
+
+        let tmp = self.dir.join(format!("{}.tmp", CURSOR_FILE));
+        {
+            let mut file = File::create(&tmp)?;
+            writeln!(file, "{}", seq)?;
+            file.sync_all()?;
+        }
+        std::fs::rename(&tmp, self.dir.join(CURSOR_FILE))?;
+        sync_dir(&self.dir)?;
+        self.cursor = seq;
+
+        Ok(())
+    }
+
+    // The seq of the last acknowledged event
+    pub fn cursor(&self) -> u64 {
+        self.cursor
+    }
+}
+
+// Writes the input to a new outbox, an existing one has to be for the same
+// input
+fn check_input(dir: &Path, input: &str) -> Result<()> {
+    let filename = dir.join(INPUT_FILE);
+    match std::fs::read_to_string(&filename) {
+        Ok(existing) if existing.trim_end() == input => Ok(()),
+        Ok(existing) => Err(anyhow::anyhow!(
+            "outbox in {} was written for input {}, not {}, a different input needs its own outbox",
+            dir.display(),
+            existing.trim_end(),
+            input
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let tmp = dir.join(format!("{}.tmp", INPUT_FILE));
+            {
+                let mut file = File::create(&tmp)?;
+                writeln!(file, "{}", input)?;
+                file.sync_all()?;
+            }
+            std::fs::rename(&tmp, &filename)?;
+            sync_dir(dir)?;
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn read_cursor(dir: &Path) -> Result<u64> {
+    match std::fs::read_to_string(dir.join(CURSOR_FILE)) {
+        Ok(cursor) => Ok(cursor.trim().parse()?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Reads up to `max` events after `after` from the log. A line that is still
+// being written is left out.
+fn read_events(path: &Path, after: u64, max: Option<usize>) -> Result<Vec<OutboxEvent>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut events = Vec::new();
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    while max.is_none_or(|max| events.len() < max) {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 || line.last() != Some(&b'\n') {
+            break;
+        }
+        let event: OutboxEvent = serde_json::from_slice(&line)?;
+        if event.seq > after {
+            events.push(event);
+        }
+    }
+    Ok(events)
+}
+
+// Makes a rename in the directory durable
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
@@ -0,0 +1,125 @@
+use anyhow::Result;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::clients::ClientIdKind;
+use crate::risk::RiskRule;
+use crate::transactions::TransactionType;
+
+// Tunable behaviour of the processing engine. The default policy has no
+// limits, which is how the engine behaves without any options.
+//
+// It can be read from a TOML file where every key is optional and falls back
+// to its default, unknown keys are rejected so typos don't go unnoticed.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnginePolicy {
+    // How long after a deposit it can still be disputed, in seconds
+    pub dispute_window_secs: Option<u64>,
+    // How long after a dispute was opened it can be charged back, in seconds
+    pub chargeback_window_secs: Option<u64>,
+    // How long an authorization can be captured for, in seconds. After that
+    // it is voided when a later row's timestamp shows it expired.
+    pub authorization_expiry_secs: Option<u64>,
+    // With a window, rows are put back in order using the `seq` column and
+    // held for at most this many rows waiting for their predecessors
+    pub reorder_window: Option<usize>,
+    // The sequence number of the first row
+    pub first_seq: u64,
+    // Keep the ids of rejected deposits and withdrawals, with why they were
+    // rejected, so a later row can't reuse them
+    pub reserve_failed_ids: bool,
+    // Checked against every transaction before it is applied
+    pub risk_rules: Vec<RiskRule>,
+    // The transaction types that are still processed for a locked account
+    pub locked_allows: Vec<TransactionType>,
+    // Whether a rejected deposit or withdrawal still creates its client
+    pub create_clients_on_rejection: bool,
+    // The transaction types that can be disputed, disputes against other
+    // types are accepted but don't move anything
+    pub disputable: Vec<TransactionType>,
+    // The client ids transactions can have, wider ids are rejected
+    pub client_ids: ClientIdKind,
+    // Settings for the clients of each account tier, by the tier named in
+    // the client-profile file. Clients without a tier, or with one that
+    // isn't here, get the defaults.
+    pub tiers: BTreeMap<String, TierPolicy>,
+}
+
+// What a tier changes for its clients
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TierPolicy {
+    // How far below zero withdrawals can take the balances, for clients
+    // whose profile doesn't set a limit of its own
+    pub overdraft_limit: Option<Decimal>,
+}
+
+impl Default for EnginePolicy {
+    fn default() -> Self {
+        Self {
+            dispute_window_secs: None,
+            chargeback_window_secs: None,
+            authorization_expiry_secs: None,
+            reorder_window: None,
+            first_seq: 1,
+            reserve_failed_ids: false,
+            risk_rules: Vec::new(),
+            locked_allows: Vec::new(),
+            create_clients_on_rejection: true,
+            disputable: vec![TransactionType::Deposit],
+            client_ids: ClientIdKind::default(),
+            tiers: BTreeMap::new(),
+        }
+    }
+}
+
+impl EnginePolicy {
+    pub fn from_toml_str(config: &str) -> Result<Self> {
+        let policy: Self = toml::from_str(config)?;
+        for rule in &policy.risk_rules {
+            rule.validate()?;
+        }
+        if policy.disputable.iter().any(|tx_type| {
+            !matches!(
+                tx_type,
+                TransactionType::Deposit | TransactionType::Withdrawal
+            )
+        }) {
+            return Err(anyhow::anyhow!(
+                "only deposits and withdrawals can be disputable"
+            ));
+        }
+        for (name, tier) in &policy.tiers {
+            if tier
+                .overdraft_limit
+                .is_some_and(|limit| limit < Decimal::ZERO)
+            {
+                return Err(anyhow::anyhow!(
+                    "tier '{}' has a negative overdraft limit",
+                    name
+                ));
+            }
+        }
+        Ok(policy)
+    }
+
+    pub fn from_toml_file(filename: &str) -> Result<Self> {
+        Self::from_toml_str(&std::fs::read_to_string(filename)?)
+    }
+
+    pub fn allows_when_locked(&self, tx_type: TransactionType) -> bool {
+        self.locked_allows.contains(&tx_type)
+    }
+
+    pub fn tier(&self, tier: Option<&str>) -> Option<&TierPolicy> {
+        self.tiers.get(tier?)
+    }
+
+    pub fn is_disputable(&self, tx_type: TransactionType) -> bool {
+        self.disputable.contains(&tx_type)
+    }
+}
+
+pub const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
@@ -0,0 +1,119 @@
+use anyhow::Result;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::io;
+
+use crate::clients::{Client, ClientId};
+use crate::transactions::processing::ProcessingEvent;
+use crate::transactions::{to_4dp, Transaction, TransactionId, TransactionType};
+
+// What an applied transaction did to its client. The balances are the
+// client's before and after it, zero before the client existed.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Receipt {
+    #[serde(rename = "tx")]
+    pub tx_id: TransactionId,
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    // for disputes, resolves and chargebacks the amount of the referenced
+    // transaction, zero if it isn't disputable
+    pub amount: Decimal,
+    pub available_before: Decimal,
+    pub held_before: Decimal,
+    pub pending_before: Decimal,
+    pub total_before: Decimal,
+    pub available_after: Decimal,
+    pub held_after: Decimal,
+    pub pending_after: Decimal,
+    pub total_after: Decimal,
+    // true if this transaction locked the account
+    pub locked: bool,
+}
+
+impl Receipt {
+    pub fn new(
+        transaction: &Transaction,
+        amount: Decimal,
+        before: Option<Client>,
+        after: Option<Client>,
+    ) -> Self {
+        let before = before.unwrap_or_else(|| Client::new(transaction.client_id));
+        let after = after.unwrap_or_else(|| Client::new(transaction.client_id));
+
+        Self {
+            tx_id: transaction.tx_id,
+            client_id: transaction.client_id,
+            tx_type: transaction.tx_type,
+            amount: to_4dp(amount),
+            available_before: to_4dp(before.available),
+            held_before: to_4dp(before.held),
+            pending_before: to_4dp(before.pending),
+            total_before: to_4dp(before.total),
+            available_after: to_4dp(after.available),
+            held_after: to_4dp(after.held),
+            pending_after: to_4dp(after.pending),
+            total_after: to_4dp(after.total),
+            locked: !before.locked && after.locked,
+        }
+    }
+
+    // The receipt for an event, only transactions that were applied get one
+    pub fn from_event(event: &ProcessingEvent) -> Option<Self> {
+        match event.amount {
+            Some(amount) if !event.replay => Some(Self::new(
+                &event.transaction,
+                amount,
+                event.before,
+                event.after,
+            )),
+            _ => None,
+        }
+    }
+}
+
+enum Format<W: io::Write> {
+    Csv(Box<csv::Writer<W>>),
+    // one JSON object per line
+    Json(W),
+}
+
+// Writes receipts one at a time as they are produced, so they don't have
+// to be kept in memory for the whole run
+pub struct ReceiptWriter<W: io::Write> {
+    format: Format<W>,
+}
+
+impl<W: io::Write> ReceiptWriter<W> {
+    pub fn csv(writer: W) -> Self {
+        Self {
+            format: Format::Csv(Box::new(csv::Writer::from_writer(writer))),
+        }
+    }
+
+    pub fn json(writer: W) -> Self {
+        Self {
+            format: Format::Json(writer),
+        }
+    }
+
+    pub fn write(&mut self, receipt: &Receipt) -> Result<()> {
+        match &mut self.format {
+            Format::Csv(writer) => writer.serialize(receipt)?,
+            Format::Json(writer) => {
+                serde_json::to_writer(&mut *writer, receipt)?;
+                writeln!(writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        match &mut self.format {
+            Format::Csv(writer) => writer.flush()?,
+            Format::Json(writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
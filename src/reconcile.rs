@@ -0,0 +1,261 @@
+use anyhow::Result;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+
+use crate::clients::{Client, ClientId, ClientPool};
+
+// One row of an expected account-state file, this is the same format
+// that `ClientPool::format_for_print` produces so a previous run's
+// output can be used directly as the expectation.
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct ExpectedAccount {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    // the output only has a pending column when some client has pending
+    // funds, so a missing column means none
+    #[serde(default)]
+    pub pending: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BalanceField {
+    Available,
+    Held,
+    Pending,
+    Total,
+}
+
+impl fmt::Display for BalanceField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BalanceField::Available => write!(f, "available"),
+            BalanceField::Held => write!(f, "held"),
+            BalanceField::Pending => write!(f, "pending"),
+            BalanceField::Total => write!(f, "total"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Discrepancy {
+    // The client is in the expected file but was never created
+    MissingClient(ClientId),
+    // The client was created but is not in the expected file
+    ExtraClient(ClientId),
+    // delta is always `actual - expected`
+    Balance {
+        client_id: ClientId,
+        field: BalanceField,
+        expected: Decimal,
+        actual: Decimal,
+        delta: Decimal,
+    },
+    Locked {
+        client_id: ClientId,
+        expected: bool,
+        actual: bool,
+    },
+}
+
+impl Discrepancy {
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            Discrepancy::MissingClient(client_id) | Discrepancy::ExtraClient(client_id) => {
+                *client_id
+            }
+            Discrepancy::Balance { client_id, .. } | Discrepancy::Locked { client_id, .. } => {
+                *client_id
+            }
+        }
+    }
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Discrepancy::MissingClient(client_id) => {
+                write!(f, "client {}: missing, expected but not found", client_id)
+            }
+            Discrepancy::ExtraClient(client_id) => {
+                write!(f, "client {}: extra, found but not expected", client_id)
+            }
+            Discrepancy::Balance {
+                client_id,
+                field,
+                expected,
+                actual,
+                delta,
+            } => write!(
+                f,
+                "client {}: {} expected {:.4}, actual {:.4}, delta {:+.4}",
+                client_id, field, expected, actual, delta
+            ),
+            Discrepancy::Locked {
+                client_id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "client {}: locked expected {}, actual {}",
+                client_id, expected, actual
+            ),
+        }
+    }
+}
+
+// The result of comparing a ClientPool against an expected account state.
+// Discrepancies are ordered by client id.
+#[derive(Debug, Default)]
+pub struct Reconciliation {
+    pub matched: usize,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl Reconciliation {
+    pub fn is_clean(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+
+    pub fn missing_clients(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.discrepancies.iter().filter_map(|d| match d {
+            Discrepancy::MissingClient(client_id) => Some(*client_id),
+            _ => None,
+        })
+    }
+
+    pub fn extra_clients(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.discrepancies.iter().filter_map(|d| match d {
+            Discrepancy::ExtraClient(client_id) => Some(*client_id),
+            _ => None,
+        })
+    }
+
+    pub fn mismatches(&self) -> impl Iterator<Item = &Discrepancy> {
+        self.discrepancies
+            .iter()
+            .filter(|d| matches!(d, Discrepancy::Balance { .. } | Discrepancy::Locked { .. }))
+    }
+}
+
+impl fmt::Display for Reconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "reconciliation: {} matched, {} missing, {} extra, {} mismatched fields",
+            self.matched,
+            self.missing_clients().count(),
+            self.extra_clients().count(),
+            self.mismatches().count()
+        )?;
+        for discrepancy in &self.discrepancies {
+            writeln!(f, "  {}", discrepancy)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn read_expected_accounts(filename: &str) -> Result<BTreeMap<ClientId, ExpectedAccount>> {
+    let file = std::fs::File::open(filename)?;
+    let buf = io::BufReader::new(file);
+
+    // same reader settings as the transaction input, the printed output
+    // has a space after every comma
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(buf);
+
+    let mut accounts = BTreeMap::new();
+
+    for result in reader.deserialize() {
+        let record: ExpectedAccount = result?;
+        if accounts.insert(record.client_id, record).is_some() {
+            return Err(anyhow::anyhow!(
+                "client {} appears more than once in expected file",
+                record.client_id
+            ));
+        }
+    }
+
+    Ok(accounts)
+}
+
+pub fn reconcile(
+    expected: &BTreeMap<ClientId, ExpectedAccount>,
+    clients: &ClientPool,
+) -> Reconciliation {
+    let mut result = Reconciliation::default();
+
+    for (client_id, account) in expected {
+        match clients.get_client(*client_id) {
+            Some(client) => {
+                let before = result.discrepancies.len();
+                compare_client(account, client, &mut result.discrepancies);
+                if result.discrepancies.len() == before {
+                    result.matched += 1;
+                }
+            }
+            None => result
+                .discrepancies
+                .push(Discrepancy::MissingClient(*client_id)),
+        }
+    }
+
+    for client in clients.iter() {
+        if !expected.contains_key(&client.id) {
+            result
+                .discrepancies
+                .push(Discrepancy::ExtraClient(client.id));
+        }
+    }
+
+    // keep the report ordered by client, missing and extra clients are
+    // found in two separate passes above
+    result.discrepancies.sort_by_key(|d| d.client_id());
+
+    result
+}
+
+pub fn reconcile_file(filename: &str, clients: &ClientPool) -> Result<Reconciliation> {
+    let expected = read_expected_accounts(filename)?;
+    Ok(reconcile(&expected, clients))
+}
+
+fn compare_client(expected: &ExpectedAccount, actual: &Client, out: &mut Vec<Discrepancy>) {
+    let fields = [
+        (
+            BalanceField::Available,
+            expected.available,
+            actual.available,
+        ),
+        (BalanceField::Held, expected.held, actual.held),
+        (BalanceField::Pending, expected.pending, actual.pending),
+        (BalanceField::Total, expected.total, actual.total),
+    ];
+
+    for (field, expected_amount, actual_amount) in fields {
+        if expected_amount != actual_amount {
+            out.push(Discrepancy::Balance {
+                client_id: actual.id,
+                field,
+                expected: expected_amount,
+                actual: actual_amount,
+                delta: actual_amount - expected_amount,
+            });
+        }
+    }
+
+    if expected.locked != actual.locked {
+        out.push(Discrepancy::Locked {
+            client_id: actual.id,
+            expected: expected.locked,
+            actual: actual.locked,
+        });
+    }
+}
@@ -0,0 +1,323 @@
+use anyhow::Result;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::io;
+
+use crate::clients::ClientId;
+use crate::policy::SECONDS_PER_DAY;
+use crate::transactions::{Transaction, TransactionType};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    // at most `limit` matching transactions per client, over the client's
+    // last `last` transactions or over the last `window_secs` seconds
+    #[serde(rename = "velocity")]
+    Velocity,
+    // no single matching transaction over `limit`
+    #[serde(rename = "max_amount")]
+    MaxAmount,
+    // a client's withdrawals in a UTC day add up to at most `limit`
+    #[serde(rename = "daily_withdrawal_cap")]
+    DailyWithdrawalCap,
+}
+
+// What happens to a transaction that hits a rule, a transaction that hits
+// no rules is allowed
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RuleAction {
+    #[serde(rename = "reject")]
+    Reject,
+    // allowed, but reported
+    #[serde(rename = "flag")]
+    Flag,
+}
+
+impl fmt::Display for RuleAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleAction::Reject => write!(f, "reject"),
+            RuleAction::Flag => write!(f, "flag"),
+        }
+    }
+}
+
+// One row of a risk rules file. A misspelled key would otherwise leave
+// its setting out, a tier for example, so unknown keys are refused.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RiskRule {
+    pub name: String,
+    pub kind: RuleKind,
+    // the transaction type the rule applies to, all types when left empty
+    #[serde(rename = "type")]
+    pub tx_type: Option<TransactionType>,
+    // a count for velocity rules, an amount for the others
+    pub limit: Decimal,
+    #[serde(default)]
+    pub last: Option<usize>,
+    #[serde(default)]
+    pub window_secs: Option<u64>,
+    pub action: RuleAction,
+    // the account tier the rule applies to, all clients when left empty
+    #[serde(default)]
+    pub tier: Option<String>,
+}
+
+impl RiskRule {
+    pub fn validate(&self) -> Result<()> {
+        match self.kind {
+            RuleKind::Velocity => {
+                if self.last.is_some() == self.window_secs.is_some() {
+                    return Err(anyhow::anyhow!(
+                        "velocity rule '{}' needs exactly one of last or window_secs",
+                        self.name
+                    ));
+                }
+                if self.last == Some(0) || self.limit.fract() != Decimal::ZERO {
+                    return Err(anyhow::anyhow!(
+                        "velocity rule '{}' needs a whole number limit and a last of at least 1",
+                        self.name
+                    ));
+                }
+            }
+            RuleKind::MaxAmount => {}
+            RuleKind::DailyWithdrawalCap => {
+                if self
+                    .tx_type
+                    .is_some_and(|tx_type| tx_type != TransactionType::Withdrawal)
+                {
+                    return Err(anyhow::anyhow!(
+                        "daily withdrawal cap '{}' can only apply to withdrawals",
+                        self.name
+                    ));
+                }
+            }
+        }
+        if self.limit < Decimal::ZERO {
+            return Err(anyhow::anyhow!("rule '{}' has a negative limit", self.name));
+        }
+        Ok(())
+    }
+
+    fn covers_tier(&self, tier: Option<&str>) -> bool {
+        self.tier.is_none() || self.tier.as_deref() == tier
+    }
+
+    fn applies_to(&self, transaction: &Transaction) -> bool {
+        match self.kind {
+            RuleKind::DailyWithdrawalCap => transaction.tx_type == TransactionType::Withdrawal,
+            _ => self
+                .tx_type
+                .is_none_or(|tx_type| tx_type == transaction.tx_type),
+        }
+    }
+}
+
+pub fn read_risk_rules(filename: &str) -> Result<Vec<RiskRule>> {
+    let file = std::fs::File::open(filename)?;
+    let buf = io::BufReader::new(file);
+
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(buf);
+
+    let mut rules = Vec::new();
+    for result in reader.deserialize() {
+        let rule: RiskRule = result?;
+        rule.validate()?;
+        rules.push(rule);
+    }
+
+    Ok(rules)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleHit {
+    pub rule: String,
+    pub action: RuleAction,
+}
+
+// A transaction that hit a rule with the reject action
+#[derive(Debug)]
+pub struct RiskRejection(pub String);
+
+impl fmt::Display for RiskRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Error: Rejected by risk rule '{}'", self.0)
+    }
+}
+
+impl std::error::Error for RiskRejection {}
+
+// What a rule remembers about one client
+#[derive(Debug, Clone)]
+enum RuleState {
+    // whether each of the client's recent transactions matched the rule
+    LastTransactions(VecDeque<bool>),
+    // timestamps of the client's recent matching transactions
+    Window(VecDeque<u64>),
+    // the day number and the withdrawals so far that day
+    Daily(Option<u64>, Decimal),
+}
+
+// Evaluates the rules against transactions before they are applied, and
+// keeps the per-client history the rules need. Only accepted transactions
+// count towards velocity limits and daily caps.
+#[derive(Debug, Default)]
+pub struct RiskEngine {
+    rules: Vec<RiskRule>,
+    state: BTreeMap<(usize, ClientId), RuleState>,
+}
+
+impl RiskEngine {
+    pub fn new(rules: Vec<RiskRule>) -> Self {
+        Self {
+            rules,
+            state: BTreeMap::new(),
+        }
+    }
+
+    // A copy with just the given clients' history, for trying out
+    // transactions without touching this one
+    pub(crate) fn scratch(&self, client_ids: &BTreeSet<ClientId>) -> RiskEngine {
+        RiskEngine {
+            rules: self.rules.clone(),
+            state: self
+                .state
+                .iter()
+                .filter(|((_, client_id), _)| client_ids.contains(client_id))
+                .map(|(key, state)| (*key, state.clone()))
+                .collect(),
+        }
+    }
+
+    // Takes over the history of a scratch copy
+    pub(crate) fn commit(&mut self, scratch: RiskEngine) {
+        self.state.extend(scratch.state);
+    }
+
+    // All the rules the transaction would hit if it was applied now, for a
+    // client of the given tier
+    pub fn check(&self, transaction: &Transaction, tier: Option<&str>) -> Vec<RuleHit> {
+        self.rules
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.covers_tier(tier))
+            .filter(|(index, rule)| self.hits(*index, rule, transaction))
+            .map(|(_, rule)| RuleHit {
+                rule: rule.name.clone(),
+                action: rule.action,
+            })
+            .collect()
+    }
+
+    // Adds an accepted transaction to the client's history, rules for other
+    // tiers don't keep one
+    pub fn record(&mut self, transaction: &Transaction, tier: Option<&str>) {
+        for (index, rule) in self.rules.iter().enumerate() {
+            if !rule.covers_tier(tier) {
+                continue;
+            }
+            let matches = rule.applies_to(transaction);
+            let key = (index, transaction.client_id);
+
+            match rule.kind {
+                RuleKind::Velocity => match (rule.last, rule.window_secs) {
+                    (Some(last), _) => {
+                        let state = self
+                            .state
+                            .entry(key)
+                            .or_insert_with(|| RuleState::LastTransactions(VecDeque::new()));
+                        if let RuleState::LastTransactions(recent) = state {
+                            recent.push_back(matches);
+                            // the transaction being checked is the last one
+                            while recent.len() >= last {
+                                recent.pop_front();
+                            }
+                        }
+                    }
+                    (None, Some(window_secs)) => {
+                        let timestamp = match transaction.timestamp {
+                            Some(timestamp) if matches => timestamp,
+                            _ => continue,
+                        };
+                        let state = self
+                            .state
+                            .entry(key)
+                            .or_insert_with(|| RuleState::Window(VecDeque::new()));
+                        if let RuleState::Window(recent) = state {
+                            recent.push_back(timestamp);
+                            while recent.front().is_some_and(|oldest| {
+                                oldest.saturating_add(window_secs) < timestamp
+                            }) {
+                                recent.pop_front();
+                            }
+                        }
+                    }
+                    (None, None) => {}
+                },
+                RuleKind::DailyWithdrawalCap if matches => {
+                    let day = transaction
+                        .timestamp
+                        .map(|timestamp| timestamp / SECONDS_PER_DAY);
+                    let amount = transaction.amount.unwrap_or_default();
+                    let state = self
+                        .state
+                        .entry(key)
+                        .or_insert(RuleState::Daily(day, Decimal::ZERO));
+                    if let RuleState::Daily(state_day, total) = state {
+                        if *state_day != day {
+                            *state_day = day;
+                            *total = Decimal::ZERO;
+                        }
+                        *total += amount;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn hits(&self, index: usize, rule: &RiskRule, transaction: &Transaction) -> bool {
+        if !rule.applies_to(transaction) {
+            return false;
+        }
+        let state = self.state.get(&(index, transaction.client_id));
+
+        match rule.kind {
+            RuleKind::MaxAmount => transaction.amount.is_some_and(|amount| amount > rule.limit),
+            RuleKind::Velocity => {
+                let previous = match state {
+                    Some(RuleState::LastTransactions(recent)) => {
+                        recent.iter().filter(|matched| **matched).count()
+                    }
+                    Some(RuleState::Window(recent)) => match transaction.timestamp {
+                        Some(timestamp) => recent
+                            .iter()
+                            .filter(|at| {
+                                at.saturating_add(rule.window_secs.unwrap_or_default()) >= timestamp
+                            })
+                            .count(),
+                        // can't place the row in the window
+                        None => return false,
+                    },
+                    _ => 0,
+                };
+                // counting the transaction being checked
+                Decimal::from(previous + 1) > rule.limit
+            }
+            RuleKind::DailyWithdrawalCap => {
+                let day = transaction
+                    .timestamp
+                    .map(|timestamp| timestamp / SECONDS_PER_DAY);
+                let so_far = match state {
+                    Some(RuleState::Daily(state_day, total)) if *state_day == day => *total,
+                    _ => Decimal::ZERO,
+                };
+                so_far + transaction.amount.unwrap_or_default() > rule.limit
+            }
+        }
+    }
+}
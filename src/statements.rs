@@ -0,0 +1,167 @@
+use anyhow::Result;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+
+use crate::clients::{ClientId, ClientProfile};
+use crate::transactions::processing::ProcessingEvent;
+use crate::transactions::{to_4dp, TransactionId, TransactionType};
+
+// One line of a client's statement, the balances are the client's
+// balances after the transaction was applied (or rejected).
+#[derive(Serialize, Debug, Clone)]
+pub struct StatementLine {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    #[serde(rename = "tx")]
+    pub tx_id: TransactionId,
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    pub amount: Option<Decimal>,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub pending: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    // set to "locked" on the transaction that locked the account
+    pub event: Option<String>,
+    pub status: StatementStatus,
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatementStatus {
+    #[serde(rename = "accepted")]
+    Accepted,
+    #[serde(rename = "rejected")]
+    Rejected,
+}
+
+// A statement line with its client's profile, for JSON statements
+#[derive(Serialize)]
+struct ProfiledLine<'a> {
+    #[serde(flatten)]
+    line: &'a StatementLine,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<&'a ClientProfile>,
+}
+
+// Collects statement lines from processing events, either for a single
+// client or for all of them. Lines are kept in input order per client.
+#[derive(Debug, Default)]
+pub struct StatementBuilder {
+    client_id: Option<ClientId>,
+    statements: BTreeMap<ClientId, Vec<StatementLine>>,
+    // written out with the lines of their clients
+    profiles: BTreeMap<ClientId, ClientProfile>,
+}
+
+impl StatementBuilder {
+    pub fn new(client_id: Option<ClientId>) -> Self {
+        Self {
+            client_id,
+            statements: BTreeMap::new(),
+            profiles: BTreeMap::new(),
+        }
+    }
+
+    // Statements that show what the profiles say about each client
+    pub fn with_profiles(mut self, profiles: BTreeMap<ClientId, ClientProfile>) -> Self {
+        self.profiles = profiles;
+        self
+    }
+
+    pub fn record(&mut self, event: &ProcessingEvent) {
+        let transaction = &event.transaction;
+
+        if let Some(client_id) = self.client_id {
+            if client_id != transaction.client_id {
+                return;
+            }
+        }
+
+        // rejected transactions against clients that were never created
+        // still show up, with empty balances
+        let zero_val = Decimal::new(0, 4);
+        let (available, held, pending, total, locked) = match event.after {
+            Some(client) => (
+                client.available,
+                client.held,
+                client.pending,
+                client.total,
+                client.locked,
+            ),
+            None => (zero_val, zero_val, zero_val, zero_val, false),
+        };
+
+        let line = StatementLine {
+            client_id: transaction.client_id,
+            tx_id: transaction.tx_id,
+            tx_type: transaction.tx_type,
+            amount: event.amount.or(transaction.amount).map(to_4dp),
+            available: to_4dp(available),
+            held: to_4dp(held),
+            pending: to_4dp(pending),
+            total: to_4dp(total),
+            locked,
+            event: event.locked_account().then(|| String::from("locked")),
+            status: if event.is_accepted() {
+                StatementStatus::Accepted
+            } else {
+                StatementStatus::Rejected
+            },
+            reason: event.rejection.clone(),
+        };
+
+        self.statements
+            .entry(transaction.client_id)
+            .or_default()
+            .push(line);
+    }
+
+    pub fn statement(&self, client_id: ClientId) -> &[StatementLine] {
+        self.statements
+            .get(&client_id)
+            .map(|lines| lines.as_slice())
+            .unwrap_or_default()
+    }
+
+    // all lines, ordered by client and then by input order
+    pub fn lines(&self) -> impl Iterator<Item = &StatementLine> {
+        self.statements.values().flatten()
+    }
+
+    // With profiles every line gets the profile columns after its own,
+    // empty for clients without a profile
+    pub fn write_csv<W: io::Write>(&self, writer: W) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        for line in self.lines() {
+            if self.profiles.is_empty() {
+                writer.serialize(line)?;
+            } else {
+                let profile = self
+                    .profiles
+                    .get(&line.client_id)
+                    .cloned()
+                    .unwrap_or_else(|| ClientProfile::new(line.client_id));
+                writer.serialize((line, profile))?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    // Lines of clients with a profile get it as a `profile` object
+    pub fn write_json<W: io::Write>(&self, writer: W) -> Result<()> {
+        let lines: Vec<ProfiledLine> = self
+            .lines()
+            .map(|line| ProfiledLine {
+                line,
+                profile: self.profiles.get(&line.client_id),
+            })
+            .collect();
+        serde_json::to_writer_pretty(writer, &lines)?;
+        Ok(())
+    }
+}
@@ -1,11 +1,181 @@
+use rust_decimal::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter::Peekable;
+use std::ops::RangeBounds;
 
-use super::{Transaction, TransactionId};
+use super::{Transaction, TransactionId, TransactionType};
+use crate::clients::ClientId;
 
+// How much of each processed transaction is kept around.
+//
+// `Full` keeps every transaction as is. `Compact` only keeps what a dispute
+// needs (amount, client and dispute state) for deposits, and only the id of
+// every other transaction so duplicates can still be detected. Rejected
+// transactions with a reserved id are always kept in full.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Retention {
+    #[default]
+    #[serde(rename = "full")]
+    Full,
+    #[serde(rename = "compact")]
+    Compact,
+}
+
+// The retained part of a deposit, the amount is stored in units of 0.0001
+// and the client as its index in the tree's `CompactClients`, so the whole
+// record fits in 16 bytes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct CompactDeposit {
+    amount: i64,
+    client: u32,
+    in_dispute: bool,
+    dispute_action: Option<TransactionType>,
+}
+
+const _: () = assert!(std::mem::size_of::<CompactDeposit>() == 16);
+
+impl CompactDeposit {
+    // Returns None for deposits without an amount, or with an amount that
+    // doesn't fit in 4 decimal places, those are kept in full instead
+    fn from_transaction(transaction: &Transaction, clients: &mut CompactClients) -> Option<Self> {
+        let mut amount = transaction.amount?;
+        if amount.scale() > 4 {
+            return None;
+        }
+        amount.rescale(4);
+        if amount.scale() != 4 {
+            return None;
+        }
+
+        Some(Self {
+            amount: i64::try_from(amount.mantissa()).ok()?,
+            client: clients.index(transaction.client_id)?,
+            in_dispute: transaction.in_dispute,
+            dispute_action: transaction.dispute_action,
+        })
+    }
+
+    fn to_transaction(
+        self,
+        tx_id: TransactionId,
+        times: Option<&CompactTimes>,
+        clients: &CompactClients,
+    ) -> Transaction {
+        Transaction {
+            tx_type: TransactionType::Deposit,
+            client_id: clients.get(self.client),
+            tx_id,
+            amount: Some(Decimal::new(self.amount, 4)),
+            in_dispute: self.in_dispute,
+            timestamp: times.and_then(|times| times.timestamp),
+            disputed_at: times.and_then(|times| times.disputed_at),
+            dispute_action: self.dispute_action,
+            failure: None,
+            settlement: None,
+            captured: None,
+            seq: None,
+            batch: None,
+        }
+    }
+}
+
+// The clients of the compact deposits, each one kept once however many
+// deposits it has. Client ids can be 16 bytes, an index is 4.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct CompactClients {
+    ids: Vec<ClientId>,
+    indexes: BTreeMap<ClientId, u32>,
+}
+
+impl CompactClients {
+    // Returns None once there are more clients than a u32 can count
+    fn index(&mut self, client_id: ClientId) -> Option<u32> {
+        if let Some(index) = self.indexes.get(&client_id) {
+            return Some(*index);
+        }
+        let index = u32::try_from(self.ids.len()).ok()?;
+        self.ids.push(client_id);
+        self.indexes.insert(client_id, index);
+        Some(index)
+    }
+
+    fn get(&self, index: u32) -> ClientId {
+        self.ids[index as usize]
+    }
+}
+
+// Timestamps of a compact deposit, only stored for deposits that have any
+// so inputs without timestamps don't pay for them
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct CompactTimes {
+    timestamp: Option<u64>,
+    disputed_at: Option<u64>,
+}
+
+// A set of transaction ids stored as 64 bit words of a bitmap. Ids tend to
+// be close together, so this is a lot smaller than a BTreeSet of the ids.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct IdSet {
+    words: BTreeMap<u32, u64>,
+    len: usize,
+}
+
+impl IdSet {
+    fn position(tx_id: &TransactionId) -> (u32, u64) {
+        (tx_id.0 / 64, 1 << (tx_id.0 % 64))
+    }
+
+    fn insert(&mut self, tx_id: TransactionId) {
+        let (word, bit) = Self::position(&tx_id);
+        let bits = self.words.entry(word).or_default();
+        if *bits & bit == 0 {
+            *bits |= bit;
+            self.len += 1;
+        }
+    }
+
+    fn contains(&self, tx_id: &TransactionId) -> bool {
+        let (word, bit) = Self::position(tx_id);
+        self.words.get(&word).is_some_and(|bits| bits & bit != 0)
+    }
+
+    fn remove(&mut self, tx_id: &TransactionId) {
+        let (word, bit) = Self::position(tx_id);
+        if let Some(bits) = self.words.get_mut(&word) {
+            if *bits & bit != 0 {
+                *bits &= !bit;
+                self.len -= 1;
+            }
+            if *bits == 0 {
+                self.words.remove(&word);
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+// The transactions are keyed by TransactionId, with secondary indexes by
+// ClientId and for open disputes. The indexes are only changed through the
+// methods below, which is why there is no `get_mut`.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TransactionTree {
+    retention: Retention,
+    // every transaction with `Full` retention, with `Compact` retention only
+    // the deposits that can't be made compact
     transactions: BTreeMap<TransactionId, Transaction>,
+    compact_deposits: BTreeMap<TransactionId, CompactDeposit>,
+    compact_times: BTreeMap<TransactionId, CompactTimes>,
+    compact_clients: CompactClients,
+    // ids that are only kept for duplicate detection
+    id_only: IdSet,
+    // only kept with `Full` retention, `Compact` retention scans instead
+    by_client: BTreeMap<ClientId, BTreeSet<TransactionId>>,
+    // ordered by client first so one client's disputes are a range
+    open_disputes: BTreeSet<(ClientId, TransactionId)>,
 }
 
 impl Default for TransactionTree {
@@ -16,24 +186,242 @@ impl Default for TransactionTree {
 
 impl TransactionTree {
     pub fn new() -> Self {
+        Self::with_retention(Retention::Full)
+    }
+
+    pub fn with_retention(retention: Retention) -> Self {
         Self {
+            retention,
             transactions: BTreeMap::new(),
+            compact_deposits: BTreeMap::new(),
+            compact_times: BTreeMap::new(),
+            compact_clients: CompactClients::default(),
+            id_only: IdSet::default(),
+            by_client: BTreeMap::new(),
+            open_disputes: BTreeSet::new(),
         }
     }
 
+    pub fn retention(&self) -> Retention {
+        self.retention
+    }
+
     pub fn insert(&mut self, transaction: Transaction) {
-        self.transactions.insert(transaction.tx_id, transaction);
+        if let Some(replaced) = self.remove(&transaction.tx_id) {
+            self.unindex(&replaced);
+        }
+
+        match self.retention {
+            Retention::Full => {
+                self.transactions.insert(transaction.tx_id, transaction);
+            }
+            // rejected transactions are rare, and keep their failure reason,
+            // authorizations are needed in full until they are settled
+            Retention::Compact
+                if transaction.failure.is_some()
+                    || transaction.tx_type == TransactionType::Authorize =>
+            {
+                self.transactions.insert(transaction.tx_id, transaction);
+            }
+            Retention::Compact if transaction.tx_type == TransactionType::Deposit => {
+                match CompactDeposit::from_transaction(&transaction, &mut self.compact_clients) {
+                    Some(compact) => {
+                        self.compact_deposits.insert(transaction.tx_id, compact);
+                        if transaction.timestamp.is_some() || transaction.disputed_at.is_some() {
+                            let times = CompactTimes {
+                                timestamp: transaction.timestamp,
+                                disputed_at: transaction.disputed_at,
+                            };
+                            self.compact_times.insert(transaction.tx_id, times);
+                        }
+                    }
+                    None => {
+                        self.transactions.insert(transaction.tx_id, transaction);
+                    }
+                }
+            }
+            Retention::Compact => {
+                self.id_only.insert(transaction.tx_id);
+                return;
+            }
+        }
+
+        self.index(&transaction);
     }
 
     pub fn contains(&self, tx_id: &TransactionId) -> bool {
         self.transactions.contains_key(tx_id)
+            || self.compact_deposits.contains_key(tx_id)
+            || self.id_only.contains(tx_id)
+    }
+
+    // Returns None for transactions that were only kept by id
+    pub fn get(&self, tx_id: &TransactionId) -> Option<Transaction> {
+        if let Some(transaction) = self.transactions.get(tx_id) {
+            return Some(*transaction);
+        }
+        self.compact_deposits.get(tx_id).map(|compact| {
+            compact.to_transaction(*tx_id, self.compact_times.get(tx_id), &self.compact_clients)
+        })
+    }
+
+    // Replaces the stored state of an existing transaction, returns false if
+    // the transaction does not exist
+    pub fn update(&mut self, transaction: Transaction) -> bool {
+        if !self.contains(&transaction.tx_id) {
+            return false;
+        }
+        self.insert(transaction);
+        true
+    }
+
+    // Returns false if the transaction does not exist
+    pub fn set_in_dispute(&mut self, tx_id: &TransactionId, in_dispute: bool) -> bool {
+        let client_id = if let Some(transaction) = self.transactions.get_mut(tx_id) {
+            transaction.in_dispute = in_dispute;
+            transaction.client_id
+        } else if let Some(compact) = self.compact_deposits.get_mut(tx_id) {
+            compact.in_dispute = in_dispute;
+            self.compact_clients.get(compact.client)
+        } else {
+            return false;
+        };
+
+        if in_dispute {
+            self.open_disputes.insert((client_id, *tx_id));
+        } else {
+            self.open_disputes.remove(&(client_id, *tx_id));
+        }
+        true
     }
 
-    pub fn get(&self, tx_id: &TransactionId) -> Option<&Transaction> {
-        self.transactions.get(tx_id)
+    pub fn len(&self) -> usize {
+        self.transactions.len() + self.compact_deposits.len() + self.id_only.len()
     }
 
-    pub fn get_mut(&mut self, client_id: &TransactionId) -> Option<&mut Transaction> {
-        self.transactions.get_mut(client_id)
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // All of a client's retained transactions, ordered by TransactionId
+    pub fn transactions_for_client(
+        &self,
+        client_id: ClientId,
+    ) -> Box<dyn Iterator<Item = Transaction> + '_> {
+        match self.retention {
+            Retention::Full => Box::new(
+                self.by_client
+                    .get(&client_id)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(move |tx_id| self.get(tx_id)),
+            ),
+            Retention::Compact => {
+                Box::new(self.range(..).filter(move |tx| tx.client_id == client_id))
+            }
+        }
+    }
+
+    pub fn open_disputes_for_client(
+        &self,
+        client_id: ClientId,
+    ) -> impl Iterator<Item = Transaction> + '_ {
+        self.open_disputes
+            .range((client_id, TransactionId(u32::MIN))..=(client_id, TransactionId(u32::MAX)))
+            .filter_map(move |(_, tx_id)| self.get(tx_id))
+    }
+
+    // All open disputes, ordered by ClientId and then TransactionId
+    pub fn open_disputes(&self) -> impl Iterator<Item = Transaction> + '_ {
+        self.open_disputes
+            .iter()
+            .filter_map(move |(_, tx_id)| self.get(tx_id))
+    }
+
+    // Retained transactions in the range, ordered by TransactionId
+    pub fn range<R>(&self, range: R) -> impl Iterator<Item = Transaction> + '_
+    where
+        R: RangeBounds<TransactionId> + Clone,
+    {
+        MergeById {
+            full: self.transactions.range(range.clone()).peekable(),
+            compact: self.compact_deposits.range(range).peekable(),
+            compact_times: &self.compact_times,
+            compact_clients: &self.compact_clients,
+        }
+    }
+
+    fn remove(&mut self, tx_id: &TransactionId) -> Option<Transaction> {
+        if let Some(transaction) = self.transactions.remove(tx_id) {
+            return Some(transaction);
+        }
+        if let Some(compact) = self.compact_deposits.remove(tx_id) {
+            let times = self.compact_times.remove(tx_id);
+            return Some(compact.to_transaction(*tx_id, times.as_ref(), &self.compact_clients));
+        }
+        self.id_only.remove(tx_id);
+        None
+    }
+
+    fn index(&mut self, transaction: &Transaction) {
+        if self.retention == Retention::Full {
+            self.by_client
+                .entry(transaction.client_id)
+                .or_default()
+                .insert(transaction.tx_id);
+        }
+        if transaction.in_dispute {
+            self.open_disputes
+                .insert((transaction.client_id, transaction.tx_id));
+        }
+    }
+
+    fn unindex(&mut self, transaction: &Transaction) {
+        if let Some(tx_ids) = self.by_client.get_mut(&transaction.client_id) {
+            tx_ids.remove(&transaction.tx_id);
+            if tx_ids.is_empty() {
+                self.by_client.remove(&transaction.client_id);
+            }
+        }
+        self.open_disputes
+            .remove(&(transaction.client_id, transaction.tx_id));
+    }
+}
+
+// Merges the full and compact maps back into one ordered sequence, a
+// transaction id is only ever in one of them
+struct MergeById<'a, F, C>
+where
+    F: Iterator,
+    C: Iterator,
+{
+    full: Peekable<F>,
+    compact: Peekable<C>,
+    compact_times: &'a BTreeMap<TransactionId, CompactTimes>,
+    compact_clients: &'a CompactClients,
+}
+
+impl<'a, F, C> Iterator for MergeById<'a, F, C>
+where
+    F: Iterator<Item = (&'a TransactionId, &'a Transaction)>,
+    C: Iterator<Item = (&'a TransactionId, &'a CompactDeposit)>,
+{
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let take_full = match (self.full.peek(), self.compact.peek()) {
+            (Some((full_id, _)), Some((compact_id, _))) => full_id < compact_id,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => return None,
+        };
+
+        if take_full {
+            self.full.next().map(|(_, tx)| *tx)
+        } else {
+            self.compact.next().map(|(tx_id, compact)| {
+                compact.to_transaction(*tx_id, self.compact_times.get(tx_id), self.compact_clients)
+            })
+        }
     }
 }
@@ -1,14 +1,29 @@
 use crate::clients::ClientId;
 use rust_decimal::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 pub mod management;
 pub mod processing;
+pub mod reorder;
+pub mod storage;
 
 // allow for copying, serialization, equality testing and sorting
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct TransactionId(u32);
 
+impl From<u32> for TransactionId {
+    fn from(id: u32) -> Self {
+        Self(id)
+    }
+}
+
+impl From<TransactionId> for u32 {
+    fn from(id: TransactionId) -> Self {
+        id.0
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum TransactionType {
     #[serde(rename = "deposit")]
@@ -21,8 +36,68 @@ pub enum TransactionType {
     Resolve,
     #[serde(rename = "chargeback")]
     Chargeback,
+    // moves funds from available to pending until they are captured or voided
+    #[serde(rename = "authorize")]
+    Authorize,
+    // settles an authorization, for its whole amount or a part of it
+    #[serde(rename = "capture")]
+    Capture,
+    // releases an authorization back to available
+    #[serde(rename = "void")]
+    Void,
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransactionType::Deposit => write!(f, "deposit"),
+            TransactionType::Withdrawal => write!(f, "withdrawal"),
+            TransactionType::Dispute => write!(f, "dispute"),
+            TransactionType::Resolve => write!(f, "resolve"),
+            TransactionType::Chargeback => write!(f, "chargeback"),
+            TransactionType::Authorize => write!(f, "authorize"),
+            TransactionType::Capture => write!(f, "capture"),
+            TransactionType::Void => write!(f, "void"),
+        }
+    }
+}
+
+// Why a deposit or withdrawal was rejected, kept with the transaction when
+// the policy reserves the ids of rejected transactions
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureReason {
+    #[serde(rename = "locked")]
+    AccountLocked,
+    #[serde(rename = "missing_amount")]
+    MissingAmount,
+    #[serde(rename = "negative_amount")]
+    NegativeAmount,
+    #[serde(rename = "insufficient_funds")]
+    InsufficientFunds,
+    #[serde(rename = "invalid_client")]
+    InvalidClient,
+    #[serde(rename = "risk_rule")]
+    RiskRule,
+    #[serde(rename = "other")]
+    Other,
 }
 
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FailureReason::AccountLocked => write!(f, "account locked"),
+            FailureReason::MissingAmount => write!(f, "missing amount"),
+            FailureReason::NegativeAmount => write!(f, "negative amount"),
+            FailureReason::InsufficientFunds => write!(f, "insufficient funds"),
+            FailureReason::InvalidClient => write!(f, "invalid client state"),
+            FailureReason::RiskRule => write!(f, "risk rule"),
+            FailureReason::Other => write!(f, "other"),
+        }
+    }
+}
+
+impl std::error::Error for FailureReason {}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct Transaction {
     #[serde(rename = "type")]
@@ -32,6 +107,79 @@ pub struct Transaction {
     #[serde(rename = "tx")]
     pub tx_id: TransactionId,
     pub amount: Option<Decimal>, // using this Decimal type allows for desired precision
-    #[serde(default)] // useful for seeing disputes, defaults to false
+    // in_dispute, disputed_at, dispute_action, failure, settlement and
+    // captured are the engine's own state. They are never read from the input, so a row can't
+    // open a dispute or settle an authorization by setting a column
+    #[serde(skip_deserializing)] // useful for seeing disputes, defaults to false
     pub in_dispute: bool,
+    // optional column, seconds since the unix epoch
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    #[serde(skip_deserializing)] // the dispute's timestamp while the transaction is in dispute
+    pub disputed_at: Option<u64>,
+    #[serde(skip_deserializing)]
+    // the last dispute, resolve or chargeback applied to this transaction
+    pub dispute_action: Option<TransactionType>,
+    #[serde(skip_deserializing)] // set when the transaction was rejected but its id is reserved
+    pub failure: Option<FailureReason>,
+    // the capture or void that settled an authorization, kept apart from
+    // the dispute state
+    #[serde(skip_deserializing)]
+    pub settlement: Option<TransactionType>,
+    // the amount a captured authorization was captured for
+    #[serde(skip_deserializing)]
+    pub captured: Option<Decimal>,
+    // optional column, the position of the row in the upstream feed
+    #[serde(default)]
+    pub seq: Option<u64>,
+    // optional column, consecutive rows with the same batch id are applied
+    // all together or not at all
+    #[serde(default)]
+    pub batch: Option<u32>,
+}
+
+impl Transaction {
+    // A transaction like a row without any of the optional columns
+    pub fn new(
+        tx_type: TransactionType,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: Option<Decimal>,
+    ) -> Self {
+        Self {
+            tx_type,
+            client_id,
+            tx_id,
+            amount,
+            in_dispute: false,
+            timestamp: None,
+            disputed_at: None,
+            dispute_action: None,
+            failure: None,
+            settlement: None,
+            captured: None,
+            seq: None,
+            batch: None,
+        }
+    }
+
+    // The transaction as it arrives, without any of the engine's own state
+    pub fn input(self) -> Self {
+        Self {
+            in_dispute: false,
+            disputed_at: None,
+            dispute_action: None,
+            failure: None,
+            settlement: None,
+            captured: None,
+            ..self
+        }
+    }
+}
+
+// Amounts are printed with 4 decimal places, in the client state as well
+// as in statements, receipts and events
+pub(crate) fn to_4dp(mut amount: Decimal) -> Decimal {
+    amount.rescale(4);
+    amount
 }
@@ -1,113 +1,866 @@
-use crate::clients::{Client, ClientPool};
+use crate::clients::{Client, ClientId, ClientPool};
+use crate::outbox::Outbox;
+use crate::policy::EnginePolicy;
+use crate::receipts::Receipt;
+use crate::risk::{RiskEngine, RiskRejection, RuleAction, RuleHit};
 use anyhow::Result;
 use rust_decimal::prelude::*;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::time::{Duration, Instant};
 
-use super::{management::TransactionTree, Transaction, TransactionType};
+use super::{
+    reorder::{ReorderBuffer, Reordered},
+    storage::{StagedStore, StoreError, TransactionStore},
+    FailureReason, Transaction, TransactionType,
+};
 
-pub fn process_transactions(
+// Describes what happened to a single input transaction. `before` and
+// `after` are the client's state around the transaction, `None` when the
+// client did not exist at that point.
+#[derive(Debug, Clone)]
+pub struct ProcessingEvent {
+    pub transaction: Transaction,
+    // the amount that was moved by this transaction, for disputes, resolves
+    // and chargebacks this is the amount of the referenced transaction
+    pub amount: Option<Decimal>,
+    pub before: Option<Client>,
+    pub after: Option<Client>,
+    pub rejection: Option<String>,
+    // an exact replay of an earlier row, acknowledged without being applied
+    pub replay: bool,
+    // the rejection needs attention, for example a conflicting duplicate id
+    pub alert: bool,
+    // the risk rules the transaction hit, with the reject or flag action
+    pub rule_hits: Vec<RuleHit>,
+    // how long applying (or rejecting) the transaction took
+    pub elapsed: Duration,
+}
+
+// A row that reuses a transaction id (or repeats a dispute, resolve or
+// chargeback) for something other than an exact replay
+#[derive(Debug)]
+pub struct DuplicateConflict(String);
+
+impl fmt::Display for DuplicateConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for DuplicateConflict {}
+
+// What applying a single transaction did
+enum Applied {
+    // the amount that was moved
+    Moved(Decimal),
+    // an exact replay of an earlier row, nothing was applied
+    Replay,
+}
+
+// What applying a single transaction did, as the library API reports it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Applied(Receipt),
+    // an exact replay of an earlier row, nothing was applied
+    Replay,
+}
+
+// Why a single transaction wasn't applied
+#[derive(Debug)]
+pub enum TransactionError {
+    // the transaction was skipped, `reason` is set for the rejections that
+    // have a `FailureReason`
+    Rejected {
+        reason: Option<FailureReason>,
+        message: String,
+    },
+    // the id was reused for something other than an exact replay
+    Conflict(String),
+    // the transaction store failed, the state can't be relied on after this
+    Store(StoreError),
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransactionError::Rejected { message, .. } => write!(f, "{}", message),
+            TransactionError::Conflict(message) => write!(f, "{}", message),
+            TransactionError::Store(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+impl From<anyhow::Error> for TransactionError {
+    fn from(e: anyhow::Error) -> Self {
+        let message = e.to_string();
+        match e.downcast::<StoreError>() {
+            Ok(store_error) => TransactionError::Store(store_error),
+            Err(e) if e.is::<DuplicateConflict>() => TransactionError::Conflict(message),
+            Err(e) => TransactionError::Rejected {
+                reason: if e.is::<RiskRejection>() {
+                    Some(FailureReason::RiskRule)
+                } else {
+                    e.downcast_ref::<FailureReason>().copied()
+                },
+                message,
+            },
+        }
+    }
+}
+
+impl ProcessingEvent {
+    pub fn is_accepted(&self) -> bool {
+        self.rejection.is_none()
+    }
+
+    // true if this transaction is the one that locked the account
+    pub fn locked_account(&self) -> bool {
+        let was_locked = self.before.is_some_and(|client| client.locked);
+        let is_locked = self.after.is_some_and(|client| client.locked);
+        !was_locked && is_locked
+    }
+}
+
+pub fn process_transactions<S>(
     transactions: Vec<Transaction>,
     clients: &mut ClientPool,
-    transaction_numbers: &mut TransactionTree,
-) -> Result<()> {
+    transaction_numbers: &mut S,
+    policy: &EnginePolicy,
+) -> Result<()>
+where
+    S: TransactionStore,
+{
+    process_transactions_with_events(transactions, clients, transaction_numbers, policy, |_| {})
+}
+
+// Same as `process_transactions`, but calls `on_event` once for every input
+// transaction after it has been applied or rejected. That is in input order
+// unless the policy reorders rows by sequence number.
+pub fn process_transactions_with_events<S, F>(
+    transactions: Vec<Transaction>,
+    clients: &mut ClientPool,
+    transaction_numbers: &mut S,
+    policy: &EnginePolicy,
+    mut on_event: F,
+) -> Result<()>
+where
+    S: TransactionStore,
+    F: FnMut(&ProcessingEvent),
+{
+    let mut risk = RiskEngine::new(policy.risk_rules.clone());
+    process_with_risk(
+        transactions,
+        clients,
+        transaction_numbers,
+        policy,
+        &mut risk,
+        None,
+        |event| {
+            on_event(event);
+            Ok(())
+        },
+    )
+}
+
+// The risk engine is passed in so an `Engine` can keep its state across
+// calls. Balance changes are written to the outbox as they are committed,
+// before the clients change. An error from `on_event` stops processing like a
+// failing store.
+pub(crate) fn process_with_risk<S, F>(
+    transactions: Vec<Transaction>,
+    clients: &mut ClientPool,
+    transaction_numbers: &mut S,
+    policy: &EnginePolicy,
+    risk: &mut RiskEngine,
+    outbox: Option<&mut Outbox>,
+    on_event: F,
+) -> Result<()>
+where
+    S: TransactionStore,
+    F: FnMut(&ProcessingEvent) -> Result<()>,
+{
+    let _span = tracing::info_span!("process_transactions", rows = transactions.len()).entered();
+
+    let mut reorder_buffer = policy
+        .reorder_window
+        .map(|window| ReorderBuffer::new(policy.first_seq, window));
+    let mut batches = Batches::default();
+    let mut sinks = EventSinks { outbox, on_event };
+
     for transaction in transactions {
-        // Since dispute types don't have a transaction id
-        // we only check for deposits and withdrawals
-        if (transaction.tx_type == TransactionType::Deposit
-            || transaction.tx_type == TransactionType::Withdrawal)
-            && transaction_numbers.contains(&transaction.tx_id)
-        {
-            eprintln!("Duplicate transaction: {:?}", &transaction.tx_id);
-            continue;
+        let reordered = match reorder_buffer.as_mut() {
+            Some(buffer) => buffer.push(transaction),
+            None => vec![Reordered::Ready(transaction)],
+        };
+        for row in reordered {
+            route_row(
+                row,
+                &mut batches,
+                clients,
+                transaction_numbers,
+                policy,
+                risk,
+                &mut sinks,
+            )?;
         }
+    }
 
-        match &transaction.tx_type {
-            TransactionType::Deposit => match process_deposit(transaction, clients) {
-                // Only add the transaction to the tree if it was successfully processed
-                Ok(_) => transaction_numbers.insert(transaction),
-                Err(e) => {
-                    // Making the decision here to continue processing on an error.
-                    eprintln!(
-                        "error processing deposit {:?}, skipping due to '{}'",
-                        &transaction.tx_id, e
-                    );
+    // anything still held at the end never got its predecessors
+    if let Some(buffer) = reorder_buffer.as_mut() {
+        for row in buffer.finish() {
+            route_row(
+                row,
+                &mut batches,
+                clients,
+                transaction_numbers,
+                policy,
+                risk,
+                &mut sinks,
+            )?;
+        }
+    }
+
+    apply_batch(
+        batches.rows,
+        clients,
+        transaction_numbers,
+        policy,
+        risk,
+        &mut sinks,
+    )
+}
+
+#[derive(Default)]
+struct Batches {
+    // the rows of the batch being collected, in the order they are applied
+    rows: Vec<Reordered>,
+    // ids of the batches that were already applied
+    closed: BTreeSet<u32>,
+}
+
+// Where the outcome of a row goes once it is known
+struct EventSinks<'a, F> {
+    outbox: Option<&'a mut Outbox>,
+    on_event: F,
+}
+
+// Applies a row, or collects it when it belongs to a batch. A batch ends at
+// the first row with a different batch id, and a batch id that comes back
+// after that is rejected, it can't be part of a batch that was already
+// applied.
+fn route_row<S, F>(
+    row: Reordered,
+    batches: &mut Batches,
+    clients: &mut ClientPool,
+    transaction_numbers: &mut S,
+    policy: &EnginePolicy,
+    risk: &mut RiskEngine,
+    sinks: &mut EventSinks<F>,
+) -> Result<()>
+where
+    S: TransactionStore,
+    F: FnMut(&ProcessingEvent) -> Result<()>,
+{
+    let batch_id = row.transaction().batch;
+    let closing = batches
+        .rows
+        .first()
+        .and_then(|first| first.transaction().batch)
+        .filter(|first_id| Some(*first_id) != batch_id);
+    if let Some(closing) = closing {
+        batches.closed.insert(closing);
+        apply_batch(
+            std::mem::take(&mut batches.rows),
+            clients,
+            transaction_numbers,
+            policy,
+            risk,
+            sinks,
+        )?;
+    }
+
+    let rejection = match batch_id {
+        Some(id) if batches.closed.contains(&id) => row.rejection().or(Some(
+            "Error: Batch id was already used by an earlier run of rows",
+        )),
+        Some(_) => {
+            batches.rows.push(row);
+            return Ok(());
+        }
+        None => row.rejection(),
+    };
+    apply_reordered(
+        row,
+        rejection,
+        clients,
+        transaction_numbers,
+        policy,
+        risk,
+        sinks,
+    )
+}
+
+// Stages the rows of a batch against scratch copies of the clients, the
+// transactions and the risk history they touch. If every row goes through
+// the staged changes are committed, otherwise every row is rejected and
+// nothing changes.
+fn apply_batch<S, F>(
+    rows: Vec<Reordered>,
+    clients: &mut ClientPool,
+    transaction_numbers: &mut S,
+    policy: &EnginePolicy,
+    risk: &mut RiskEngine,
+    sinks: &mut EventSinks<F>,
+) -> Result<()>
+where
+    S: TransactionStore,
+    F: FnMut(&ProcessingEvent) -> Result<()>,
+{
+    let Some(batch_id) = rows.first().and_then(|row| row.transaction().batch) else {
+        return Ok(());
+    };
+    let _span = tracing::info_span!("batch", batch = batch_id, rows = rows.len()).entered();
+
+    // the whole batch is applied at the time of its first row, authorizations
+    // that expired before then are voided first
+    let now = rows.iter().find_map(|row| row.transaction().timestamp);
+    for event in expire_authorizations(
+        now,
+        clients,
+        transaction_numbers,
+        policy,
+        sinks.outbox.as_deref_mut(),
+    )? {
+        (sinks.on_event)(&event)?;
+    }
+
+    let client_ids: BTreeSet<ClientId> =
+        rows.iter().map(|row| row.transaction().client_id).collect();
+    let mut scratch_clients = clients.scratch(&client_ids);
+    let mut scratch_risk = risk.scratch(&client_ids);
+    let mut scratch_store = StagedStore::new(&*transaction_numbers);
+
+    let started = Instant::now();
+    let mut staged = Vec::with_capacity(rows.len());
+    let mut failure = None;
+    for row in &rows {
+        let (event, _) = apply_with_event(
+            row.transaction(),
+            row.rejection(),
+            &mut scratch_clients,
+            &mut scratch_store,
+            policy,
+            &mut scratch_risk,
+            None,
+        )?;
+        if let Some(rejection) = &event.rejection {
+            failure = Some(format!(
+                "Error: Batch {} rejected, transaction {} failed: {}",
+                batch_id,
+                u32::from(event.transaction.tx_id),
+                rejection
+            ));
+            break;
+        }
+        staged.push(event);
+    }
+
+    match failure {
+        None => {
+            scratch_store.into_writes().commit(transaction_numbers)?;
+            if let Some(outbox) = sinks.outbox.as_deref_mut() {
+                for event in &staged {
+                    outbox.record(event)?;
                 }
-            },
-            TransactionType::Withdrawal => match process_withdrawal(transaction, clients) {
-                Ok(_) => transaction_numbers.insert(transaction),
-                Err(e) => {
-                    eprintln!(
-                        "error processing withdrawal {:?}, skipping due to '{}'",
-                        &transaction.tx_id, e
-                    );
+            }
+            clients.commit(scratch_clients);
+            risk.commit(scratch_risk);
+            tracing::debug!(batch = batch_id, "batch committed");
+
+            for event in &staged {
+                (sinks.on_event)(event)?;
+            }
+        }
+        Some(reason) => {
+            tracing::warn!(batch = batch_id, reason = %reason, "batch rejected");
+
+            // every row gets the batch's reason, with the state it left untouched
+            let elapsed = started.elapsed() / rows.len() as u32;
+            for row in &rows {
+                let transaction = row.transaction();
+                let client = clients.get_client(transaction.client_id).copied();
+                (sinks.on_event)(&ProcessingEvent {
+                    transaction,
+                    amount: None,
+                    before: client,
+                    after: client,
+                    rejection: Some(reason.clone()),
+                    replay: false,
+                    alert: false,
+                    rule_hits: Vec::new(),
+                    elapsed,
+                })?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Applies a row on its own. `rejection` rejects it without trying to apply it.
+fn apply_reordered<S, F>(
+    row: Reordered,
+    rejection: Option<&'static str>,
+    clients: &mut ClientPool,
+    transaction_numbers: &mut S,
+    policy: &EnginePolicy,
+    risk: &mut RiskEngine,
+    sinks: &mut EventSinks<F>,
+) -> Result<()>
+where
+    S: TransactionStore,
+    F: FnMut(&ProcessingEvent) -> Result<()>,
+{
+    // authorizations that expired before the row are voided first
+    let expired = expire_authorizations(
+        row.transaction().timestamp,
+        clients,
+        transaction_numbers,
+        policy,
+        sinks.outbox.as_deref_mut(),
+    )?;
+    for event in &expired {
+        (sinks.on_event)(event)?;
+    }
+
+    let (event, _) = apply_with_event(
+        row.transaction(),
+        rejection,
+        clients,
+        transaction_numbers,
+        policy,
+        risk,
+        sinks.outbox.as_deref_mut(),
+    )?;
+    (sinks.on_event)(&event)
+}
+
+// Applies a single transaction, logs what happened to it and describes it
+// with an event. `rejection` rejects it without trying to apply it. A
+// balance change is written to the outbox, if there is one, before the
+// client changes. The
+// outcome comes back next to the event for callers that want the typed error,
+// only a failing store is returned as an error since processing can't go on.
+pub(crate) fn apply_with_event<S: TransactionStore>(
+    transaction: Transaction,
+    rejection: Option<&'static str>,
+    clients: &mut ClientPool,
+    transaction_numbers: &mut S,
+    policy: &EnginePolicy,
+    risk: &mut RiskEngine,
+    outbox: Option<&mut Outbox>,
+) -> Result<(ProcessingEvent, Result<Outcome, TransactionError>)> {
+    let transaction = transaction.input();
+    let before = clients.get_client(transaction.client_id).copied();
+
+    let mut rule_hits = Vec::new();
+    let started = Instant::now();
+    let result = match rejection {
+        Some(reason) => Err(anyhow::anyhow!(reason)),
+        None => process_transaction(
+            transaction,
+            clients,
+            transaction_numbers,
+            policy,
+            risk,
+            outbox,
+            &mut rule_hits,
+        ),
+    };
+    let elapsed = started.elapsed();
+
+    // A failing store can't be skipped over like an invalid transaction
+    let result = match result {
+        Err(e) if e.is::<StoreError>() => return Err(e),
+        result => result,
+    };
+    // Making the decision here to continue processing on an error.
+    log_outcome(&transaction, &result, &rule_hits);
+
+    let after = clients.get_client(transaction.client_id).copied();
+    let (amount, replay) = match &result {
+        Ok(Applied::Moved(amount)) => (Some(*amount), false),
+        Ok(Applied::Replay) => (None, true),
+        Err(_) => (None, false),
+    };
+    let event = ProcessingEvent {
+        transaction,
+        amount,
+        before,
+        after,
+        alert: result
+            .as_ref()
+            .err()
+            .is_some_and(|e| e.is::<DuplicateConflict>()),
+        rejection: result.as_ref().err().map(|e| e.to_string()),
+        replay,
+        rule_hits,
+        elapsed,
+    };
+
+    let outcome = match result {
+        Ok(Applied::Moved(amount)) => Ok(Outcome::Applied(Receipt::new(
+            &transaction,
+            amount,
+            before,
+            after,
+        ))),
+        Ok(Applied::Replay) => Ok(Outcome::Replay),
+        Err(e) => Err(TransactionError::from(e)),
+    };
+    Ok((event, outcome))
+}
+
+fn process_transaction<S: TransactionStore>(
+    transaction: Transaction,
+    clients: &mut ClientPool,
+    transaction_numbers: &mut S,
+    policy: &EnginePolicy,
+    risk: &mut RiskEngine,
+    outbox: Option<&mut Outbox>,
+    rule_hits: &mut Vec<RuleHit>,
+) -> Result<Applied> {
+    // ids that don't fit the configured type never reach the clients
+    policy.client_ids.check(transaction.client_id)?;
+
+    // replays are acknowledged, conflicts come back as an error
+    if is_replay(&transaction, transaction_numbers)? {
+        return Ok(Applied::Replay);
+    }
+
+    // the risk rules are checked before anything is applied, the client's
+    // tier picks which ones
+    let tier = clients.get_tier(transaction.client_id).map(String::from);
+    *rule_hits = risk.check(&transaction, tier.as_deref());
+    let rejected_by = rule_hits
+        .iter()
+        .find(|hit| hit.action == RuleAction::Reject)
+        .map(|hit| RiskRejection(hit.rule.clone()));
+
+    // nothing is written until the whole change has been worked out and checked
+    let proposal = match (&transaction.tx_type, rejected_by) {
+        (
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Authorize,
+            rejected_by,
+        ) => {
+            let risk_rejected = rejected_by.is_some();
+            let proposed = match (transaction.tx_type, rejected_by) {
+                (_, Some(rejection)) => Err(rejection.into()),
+                (TransactionType::Deposit, None) => process_deposit(transaction, clients, policy),
+                (TransactionType::Withdrawal, None) => {
+                    process_withdrawal(transaction, clients, policy)
                 }
-            },
-            TransactionType::Dispute => {
-                match process_dispute(transaction, clients, transaction_numbers) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        eprintln!(
-                            "error processing dispute for transaction {:?}, skipping due to '{}'",
-                            &transaction.tx_id, e
-                        );
+                (_, None) => process_authorize(transaction, clients, policy),
+            };
+
+            match proposed {
+                Ok(proposal) => proposal,
+                Err(e) => {
+                    // the row still creates its client if the policy says so,
+                    // unless the risk rules stopped it before it got that far
+                    let existed = clients.has_client(&transaction.client_id)?;
+                    if !existed && !risk_rejected && policy.create_clients_on_rejection {
+                        clients.create_client(transaction.client_id, policy);
                     }
+                    // unless the policy reserves the id, with the reason it failed
+                    if policy.reserve_failed_ids {
+                        let mut failed = transaction;
+                        failed.failure = Some(if e.is::<RiskRejection>() {
+                            FailureReason::RiskRule
+                        } else {
+                            e.downcast_ref::<FailureReason>()
+                                .copied()
+                                .unwrap_or(FailureReason::Other)
+                        });
+                        transaction_numbers.insert(failed)?;
+                    }
+                    return Err(e);
                 }
             }
-            TransactionType::Resolve => {
-                match process_resolve(transaction, clients, transaction_numbers) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        eprintln!(
-                            "error processing resolve for transaction {:?}, skipping due to '{}'",
-                            &transaction.tx_id, e
-                        );
-                    }
+        }
+        (_, Some(rejection)) => return Err(rejection.into()),
+        (TransactionType::Dispute, None) => {
+            process_dispute(transaction, clients, transaction_numbers, policy)?
+        }
+        (TransactionType::Resolve, None) => {
+            process_resolve(transaction, clients, transaction_numbers, policy)?
+        }
+        (TransactionType::Chargeback, None) => {
+            process_chargeback(transaction, clients, transaction_numbers, policy)?
+        }
+        (TransactionType::Capture, None) => {
+            process_capture(transaction, clients, transaction_numbers, policy)?
+        }
+        (TransactionType::Void, None) => process_void(transaction, clients, transaction_numbers)?,
+    };
+
+    let amount = proposal.commit(&transaction, clients, transaction_numbers, outbox)?;
+    risk.record(&transaction, tier.as_deref());
+    Ok(Applied::Moved(amount))
+}
+
+// The change a transaction would make, worked out against copies of the
+// client and the stored transaction. Nothing is written until it is
+// committed, so a rejected transaction never has anything to undo.
+struct Proposal {
+    client: Client,
+    record: Record,
+    // the amount that would be moved
+    amount: Decimal,
+}
+
+// What a proposal writes to the transaction store
+enum Record {
+    Insert(Transaction),
+    Update(Transaction),
+    Unchanged,
+}
+
+impl Proposal {
+    // Nothing changes, for disputes of transactions that aren't disputable
+    fn unchanged(client: Client) -> Self {
+        Self {
+            client,
+            record: Record::Unchanged,
+            amount: Decimal::ZERO,
+        }
+    }
+
+    // Writes the new transaction state, then the balance change to the
+    // outbox and then the new client. A failing store or outbox leaves the
+    // client as it was.
+    fn commit<S: TransactionStore>(
+        self,
+        transaction: &Transaction,
+        clients: &mut ClientPool,
+        transaction_numbers: &mut S,
+        outbox: Option<&mut Outbox>,
+    ) -> Result<Decimal> {
+        let written = match self.record {
+            Record::Insert(transaction) => {
+                transaction_numbers.insert(transaction)?;
+                Some(transaction)
+            }
+            Record::Update(transaction) => {
+                transaction_numbers.update(transaction)?;
+                Some(transaction)
+            }
+            Record::Unchanged => None,
+        };
+        if let Some(outbox) = outbox {
+            let before = clients.get_client(transaction.client_id).copied();
+            let receipt = Receipt::new(transaction, self.amount, before, Some(self.client));
+            outbox.record_receipt(&receipt, self.client.locked)?;
+        }
+        if let Some(transaction) = written {
+            clients.track_authorization(&transaction);
+        }
+        clients.add_client(self.client);
+        Ok(self.amount)
+    }
+}
+
+// Checks whether the transaction was already applied. An exact replay of the
+// same row (as an at-least-once upstream would send) returns true, a row
+// that reuses the id for something else is a `DuplicateConflict` error.
+fn is_replay<S: TransactionStore>(transaction: &Transaction, store: &S) -> Result<bool> {
+    match transaction.tx_type {
+        TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Authorize => {
+            if !store.contains(&transaction.tx_id)? {
+                return Ok(false);
+            }
+
+            match store.get(&transaction.tx_id)? {
+                // a replay of a rejected row is rejected again, but it isn't a conflict
+                Some(stored)
+                    if stored.failure.is_some()
+                        && stored.tx_type == transaction.tx_type
+                        && stored.client_id == transaction.client_id
+                        && stored.amount == transaction.amount =>
+                {
+                    Err(anyhow::anyhow!(
+                        "Error: Transaction was already rejected ({})",
+                        stored.failure.unwrap_or(FailureReason::Other)
+                    ))
                 }
+                Some(stored)
+                    if stored.tx_type == transaction.tx_type
+                        && stored.client_id == transaction.client_id
+                        && stored.amount == transaction.amount =>
+                {
+                    Ok(true)
+                }
+                Some(_) => Err(DuplicateConflict(String::from(
+                    "Error: Conflicting duplicate transaction, the id was used for a different transaction",
+                ))
+                .into()),
+                // with compact retention withdrawals are only kept by id
+                None => Err(DuplicateConflict(String::from(
+                    "Error: Duplicate transaction id, the original is not retained so it can't be compared",
+                ))
+                .into()),
             }
-            TransactionType::Chargeback => {
-                match process_chargeback(transaction, clients, transaction_numbers) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        eprintln!(
-                            "error processing chargeback for transaction {:?}, skipping due to '{}'",
-                            &transaction.tx_id, e
-                        );
+        }
+        // for these the referenced transaction remembers the last one applied,
+        // repeating it is a replay as long as it comes from the same client
+        TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+            match store.get(&transaction.tx_id)? {
+                Some(stored) if stored.dispute_action == Some(transaction.tx_type) => {
+                    if stored.client_id == transaction.client_id {
+                        Ok(true)
+                    } else {
+                        Err(DuplicateConflict(String::from(
+                            "Error: Conflicting duplicate, the transaction belongs to a different client",
+                        ))
+                        .into())
                     }
                 }
+                _ => Ok(false),
             }
         }
+        // the same for captures and voids, with the authorization's settlement.
+        // A capture without an amount captured the whole authorization
+        TransactionType::Capture | TransactionType::Void => match store.get(&transaction.tx_id)? {
+            Some(stored) if stored.settlement == Some(transaction.tx_type) => {
+                if stored.client_id != transaction.client_id {
+                    Err(DuplicateConflict(String::from(
+                            "Error: Conflicting duplicate, the authorization belongs to a different client",
+                        ))
+                        .into())
+                } else if transaction.tx_type == TransactionType::Capture
+                    && transaction.amount.or(stored.amount) != stored.captured
+                {
+                    Err(DuplicateConflict(String::from(
+                        "Error: Conflicting duplicate, the authorization was captured for a different amount",
+                    ))
+                    .into())
+                } else {
+                    Ok(true)
+                }
+            }
+            _ => Ok(false),
+        },
     }
-    Ok(())
 }
 
-fn process_deposit(transaction: Transaction, clients: &mut ClientPool) -> Result<()> {
-    let found_client = clients.has_client(&transaction.client_id)?;
+fn log_outcome(transaction: &Transaction, result: &Result<Applied>, rule_hits: &[RuleHit]) {
+    // named clients are logged as `client_name` so `client_id` stays a number
+    let client_id = transaction.client_id.number();
+    let client_name = transaction.client_id.name();
+    let tx_id = u32::from(transaction.tx_id);
+    let tx_type = transaction.tx_type;
+
+    for hit in rule_hits
+        .iter()
+        .filter(|hit| hit.action == RuleAction::Flag)
+    {
+        tracing::warn!(
+            client_id,
+            client_name,
+            tx_id,
+            %tx_type,
+            outcome = "flagged",
+            rule = %hit.rule,
+            "transaction hit a risk rule"
+        );
+    }
 
-    if !found_client {
-        // add client to pool
-        let new_client = Client::new(transaction.client_id);
-        clients.add_client(new_client);
+    match result {
+        Ok(Applied::Moved(amount)) => tracing::debug!(
+            client_id,
+            client_name,
+            tx_id,
+            %tx_type,
+            outcome = "accepted",
+            %amount,
+            "transaction applied"
+        ),
+        Ok(Applied::Replay) => tracing::info!(
+            client_id,
+            client_name,
+            tx_id,
+            %tx_type,
+            outcome = "replay",
+            "replayed transaction acknowledged"
+        ),
+        Err(e) if e.is::<DuplicateConflict>() => tracing::error!(
+            client_id,
+            client_name,
+            tx_id,
+            %tx_type,
+            outcome = "conflict",
+            reason = %e,
+            "conflicting duplicate transaction skipped"
+        ),
+        Err(e) => tracing::warn!(
+            client_id,
+            client_name,
+            tx_id,
+            %tx_type,
+            outcome = "rejected",
+            reason = %e,
+            "transaction skipped"
+        ),
     }
+}
 
-    // this is now guarenteed to not be None
-    let client = clients
-        .get_client_mut(transaction.client_id)
-        .ok_or_else(|| anyhow::anyhow!("client not found in pool: {:?}", transaction.client_id))?;
+// The error for a rejected deposit or withdrawal, it prints as the message
+// and carries the reason for when the id is reserved
+fn failed<M>(reason: FailureReason, message: M) -> anyhow::Error
+where
+    M: fmt::Display + fmt::Debug + Send + Sync + 'static,
+{
+    anyhow::Error::new(reason).context(message)
+}
 
-    // locked accounts should not continue
-    if client.locked {
-        return Err(anyhow::anyhow!(
-            "client {:?} is locked, cannot process deposit",
-            transaction.client_id
+fn process_deposit(
+    transaction: Transaction,
+    clients: &ClientPool,
+    policy: &EnginePolicy,
+) -> Result<Proposal> {
+    // a client that doesn't exist yet only gets added if the deposit goes through
+    let mut client = clients
+        .get_client(transaction.client_id)
+        .copied()
+        .unwrap_or_else(|| clients.new_client(transaction.client_id, policy));
+
+    // locked accounts should not continue, unless the policy allows the type
+    if client.locked && !policy.allows_when_locked(transaction.tx_type) {
+        return Err(failed(
+            FailureReason::AccountLocked,
+            format!(
+                "client {:?} is locked, cannot process deposit",
+                transaction.client_id
+            ),
         ));
     }
 
     // deposits should always have an amount
-    let deposit_amount = transaction
-        .amount
-        .ok_or_else(|| anyhow::anyhow!("Error: Transaction amount was not provided",))?;
+    let deposit_amount = transaction.amount.ok_or_else(|| {
+        failed(
+            FailureReason::MissingAmount,
+            "Error: Transaction amount was not provided",
+        )
+    })?;
 
     if deposit_amount < Decimal::from(0) {
-        return Err(anyhow::anyhow!("Error: Deposit amount is negative"));
+        return Err(failed(
+            FailureReason::NegativeAmount,
+            "Error: Deposit amount is negative",
+        ));
     }
 
     // deposit amount to client available balance
@@ -115,100 +868,163 @@ fn process_deposit(transaction: Transaction, clients: &mut ClientPool) -> Result
     client.total += deposit_amount;
 
     if !client.check_client_validity() {
-        // return amounts back
-        client.available -= deposit_amount;
-        client.total -= deposit_amount;
-        return Err(anyhow::anyhow!("Error: Client is invalid after deposit",));
+        return Err(failed(
+            FailureReason::InvalidClient,
+            "Error: Client is invalid after deposit",
+        ));
     }
 
-    Ok(())
+    Ok(Proposal {
+        client,
+        record: Record::Insert(transaction),
+        amount: deposit_amount,
+    })
 }
 
-fn process_withdrawal(transaction: Transaction, clients: &mut ClientPool) -> Result<()> {
-    let found_client = clients.has_client(&transaction.client_id)?;
-
-    if !found_client {
-        // add client to pool, this is okay for withdrawals without an
-        // existing client because it will create the client but it
-        // will not process the transaction
-        let new_client = Client::new(transaction.client_id);
-        clients.add_client(new_client);
-    }
-
-    // this is now guarenteed to not be None
-    let client = clients
-        .get_client_mut(transaction.client_id)
-        .ok_or_else(|| anyhow::anyhow!("client not found in pool: {:?}", transaction.client_id))?;
+fn process_withdrawal(
+    transaction: Transaction,
+    clients: &ClientPool,
+    policy: &EnginePolicy,
+) -> Result<Proposal> {
+    // a withdrawal for a client that doesn't exist yet is worked out against
+    // a new client, it can only go through with an overdraft line
+    let mut client = clients
+        .get_client(transaction.client_id)
+        .copied()
+        .unwrap_or_else(|| clients.new_client(transaction.client_id, policy));
 
-    // locked accounts should not continue
-    if client.locked {
-        return Err(anyhow::anyhow!(
-            "client {:?} is locked, cannot process withdrawal",
-            transaction.client_id
+    // locked accounts should not continue, unless the policy allows the type
+    if client.locked && !policy.allows_when_locked(transaction.tx_type) {
+        return Err(failed(
+            FailureReason::AccountLocked,
+            format!(
+                "client {:?} is locked, cannot process withdrawal",
+                transaction.client_id
+            ),
         ));
     }
 
-    let withdrawal_amount = transaction
-        .amount
-        .ok_or_else(|| anyhow::anyhow!("Error: Transaction amount was not provided",))?;
+    let withdrawal_amount = transaction.amount.ok_or_else(|| {
+        failed(
+            FailureReason::MissingAmount,
+            "Error: Transaction amount was not provided",
+        )
+    })?;
 
     if withdrawal_amount < Decimal::from(0) {
-        return Err(anyhow::anyhow!("Error: withdrawal amount is negative"));
+        return Err(failed(
+            FailureReason::NegativeAmount,
+            "Error: withdrawal amount is negative",
+        ));
     }
 
-    // Check to see if the client has enough available balance to withdraw
-    if client.available < withdrawal_amount {
-        return Err(anyhow::anyhow!(
+    // Check to see if the client has enough available balance to withdraw,
+    // counting the client's overdraft line
+    if client.available + client.overdraft_limit < withdrawal_amount {
+        return Err(failed(
+            FailureReason::InsufficientFunds,
             "Error: Client does not have enough available balance to withdraw",
         ));
     }
 
     // withdrawal amount to client available balance
-    // This mutates so we need to be sure that the transaction
-    // is valid before and after we do this
     client.available -= withdrawal_amount;
     client.total -= withdrawal_amount;
 
     if !client.check_client_validity() {
-        // if the client is invalid after the withdrawal, we need to put it back
-        client.available += withdrawal_amount;
-        client.total += withdrawal_amount;
-        return Err(anyhow::anyhow!("Error: Client is invalid after withdrawal",));
+        return Err(failed(
+            FailureReason::InvalidClient,
+            "Error: Client is invalid after withdrawal",
+        ));
     }
 
-    Ok(())
+    Ok(Proposal {
+        client,
+        record: Record::Insert(transaction),
+        amount: withdrawal_amount,
+    })
 }
 
-fn process_dispute(
+// Moves the amount from available to pending, the funds stay the client's
+// until the authorization is captured
+fn process_authorize(
     transaction: Transaction,
-    clients: &mut ClientPool,
-    transaction_tree: &mut TransactionTree,
-) -> Result<()> {
-    let found_client = clients.has_client(&transaction.client_id)?;
+    clients: &ClientPool,
+    policy: &EnginePolicy,
+) -> Result<Proposal> {
+    let mut client = clients
+        .get_client(transaction.client_id)
+        .copied()
+        .unwrap_or_else(|| clients.new_client(transaction.client_id, policy));
 
-    if !found_client {
-        // we don't want to create a client in this case
-        // we will just return an error and ignore the transaction
+    // locked accounts should not continue, unless the policy allows the type
+    if client.locked && !policy.allows_when_locked(transaction.tx_type) {
+        return Err(failed(
+            FailureReason::AccountLocked,
+            format!(
+                "client {:?} is locked, cannot process authorization",
+                transaction.client_id
+            ),
+        ));
+    }
+
+    let authorized_amount = transaction.amount.ok_or_else(|| {
+        failed(
+            FailureReason::MissingAmount,
+            "Error: Transaction amount was not provided",
+        )
+    })?;
+
+    if authorized_amount < Decimal::from(0) {
+        return Err(failed(
+            FailureReason::NegativeAmount,
+            "Error: authorization amount is negative",
+        ));
+    }
+
+    // an authorization that can expire needs a time to expire from
+    if policy.authorization_expiry_secs.is_some() && transaction.timestamp.is_none() {
         return Err(anyhow::anyhow!(
-            "Error: Client not found in pool: {:?}",
-            transaction.client_id
+            "Error: Authorization can't expire without a timestamp"
         ));
     }
 
-    // Need to get the actual transaction to get the details
-    // the dispute transaction only has the transaction id
-    let found_transaction = match transaction_tree.get_mut(&transaction.tx_id) {
-        Some(tx) => tx,
-        None => {
-            return Err(anyhow::anyhow!(
-                "Error: Provided transaction has not been processed"
-            ));
-        }
-    };
+    // the same check as a withdrawal, counting the client's overdraft line
+    if client.available + client.overdraft_limit < authorized_amount {
+        return Err(failed(
+            FailureReason::InsufficientFunds,
+            "Error: Client does not have enough available balance to authorize",
+        ));
+    }
+
+    client.available -= authorized_amount;
+    client.pending += authorized_amount;
+
+    if !client.check_client_validity() {
+        return Err(failed(
+            FailureReason::InvalidClient,
+            "Error: Client is invalid after authorization",
+        ));
+    }
 
-    // like others, we know this will succeed
-    let client = clients
-        .get_client_mut(transaction.client_id)
+    Ok(Proposal {
+        client,
+        record: Record::Insert(transaction),
+        amount: authorized_amount,
+    })
+}
+
+fn process_dispute<S: TransactionStore>(
+    transaction: Transaction,
+    clients: &ClientPool,
+    transaction_tree: &S,
+    policy: &EnginePolicy,
+) -> Result<Proposal> {
+    // we don't want to create a client in this case
+    // we will just return an error and ignore the transaction
+    let mut client = clients
+        .get_client(transaction.client_id)
+        .copied()
         .ok_or_else(|| {
             anyhow::anyhow!(
                 "Error: Client not found in pool: {:?}",
@@ -216,8 +1032,12 @@ fn process_dispute(
             )
         })?;
 
-    // locked accounts should not continue
-    if client.locked {
+    // Need to get the actual transaction to get the details
+    // the dispute transaction only has the transaction id
+    let found_transaction = find_disputable(&transaction, transaction_tree)?;
+
+    // locked accounts should not continue, unless the policy allows the type
+    if client.locked && !policy.allows_when_locked(transaction.tx_type) {
         return Err(anyhow::anyhow!(
             "client {:?} is locked, cannot process dispute",
             transaction.client_id
@@ -228,7 +1048,20 @@ fn process_dispute(
         .amount
         .ok_or_else(|| anyhow::anyhow!("Error: Transaction amount was not provided",))?;
 
-    // It only makes sense to dispute a deposit
+    // By default it only makes sense to dispute a deposit, nothing is moved
+    // for transactions that aren't disputable
+    if !policy.is_disputable(found_transaction.tx_type) {
+        return Ok(Proposal::unchanged(client));
+    }
+
+    // a transaction can only be disputed for a while after it was made
+    check_window(
+        "Dispute",
+        policy.dispute_window_secs,
+        found_transaction.timestamp,
+        transaction.timestamp,
+    )?;
+
     if found_transaction.tx_type == TransactionType::Deposit {
         // check to see if the client has enough available balance to dispute
         if client.available < dispute_amount {
@@ -238,51 +1071,109 @@ fn process_dispute(
         }
         // dispute amount to client available balance
         client.available -= dispute_amount;
-        client.held += dispute_amount;
-        // change to show the transaction is now disputed
-        found_transaction.in_dispute = true;
-
-        if !client.check_client_validity() {
-            // if the client is invalid after the dispute, we need to put it back
-            client.available += dispute_amount;
-            client.held -= dispute_amount;
-            found_transaction.in_dispute = false;
+    } else {
+        // a disputed withdrawal is credited back as held funds until it is settled
+        client.total += dispute_amount;
+    }
+    client.held += dispute_amount;
 
-            return Err(anyhow::anyhow!("Error: Client is invalid after dispute",));
-        }
+    if !client.check_client_validity() {
+        return Err(anyhow::anyhow!("Error: Client is invalid after dispute",));
     }
 
-    Ok(())
+    // change to show the transaction is now disputed
+    let mut disputed_transaction = found_transaction;
+    disputed_transaction.in_dispute = true;
+    disputed_transaction.disputed_at = transaction.timestamp;
+    disputed_transaction.dispute_action = Some(TransactionType::Dispute);
+
+    Ok(Proposal {
+        client,
+        record: Record::Update(disputed_transaction),
+        amount: dispute_amount,
+    })
 }
 
-fn process_resolve(
+fn process_resolve<S: TransactionStore>(
     transaction: Transaction,
-    clients: &mut ClientPool,
-    transaction_tree: &mut TransactionTree,
-) -> Result<()> {
-    let found_client = clients.has_client(&transaction.client_id)?;
+    clients: &ClientPool,
+    transaction_tree: &S,
+    policy: &EnginePolicy,
+) -> Result<Proposal> {
+    let mut client = clients
+        .get_client(transaction.client_id)
+        .copied()
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Error: Client not found in pool: {:?}",
+                transaction.client_id
+            )
+        })?;
 
-    if !found_client {
+    // Need to get the actual transaction to get the details
+    // the resolve transaction only has the transaction id
+    let found_transaction = find_disputable(&transaction, transaction_tree)?;
+
+    // locked accounts should not continue, unless the policy allows the type
+    if client.locked && !policy.allows_when_locked(transaction.tx_type) {
         return Err(anyhow::anyhow!(
-            "Error: Client not found in pool: {:?}",
+            "client {:?} is locked, cannot process resolve",
             transaction.client_id
         ));
     }
 
-    // Need to get the actual transaction to get the details
-    // the resolve transaction only has the transaction id
-    let found_transaction = match transaction_tree.get_mut(&transaction.tx_id) {
-        Some(tx) => tx,
-        None => {
-            return Err(anyhow::anyhow!(
-                "Error: Provided transaction has not been processed"
-            ));
-        }
-    };
+    let resolve_amount = found_transaction
+        .amount
+        .ok_or_else(|| anyhow::anyhow!("Error: Transaction amount was not provided",))?;
+
+    // it only makes sense to resolve what can be disputed
+    if !policy.is_disputable(found_transaction.tx_type) {
+        return Ok(Proposal::unchanged(client));
+    }
+
+    // check to see if the client has enough held funds to process the dispute
+    if client.held < resolve_amount {
+        return Err(anyhow::anyhow!(
+            "Error: Client does not have enough held funds to resolve",
+        ));
+    }
+
+    if !found_transaction.in_dispute {
+        return Err(anyhow::anyhow!(
+            "Error: Specified transaction is not in dispute",
+        ));
+    }
+
+    client.held -= resolve_amount;
+    if found_transaction.tx_type == TransactionType::Deposit {
+        // return the disputed amount to available balance from held balance
+        client.available += resolve_amount;
+    } else {
+        // the withdrawal stands, so the held credit is taken back
+        client.total -= resolve_amount;
+    }
+
+    if !client.check_client_validity() {
+        return Err(anyhow::anyhow!("Error: Client is invalid after resolve",));
+    }
+
+    // change to show the transaction is no longer disputed
+    Ok(Proposal {
+        client,
+        record: Record::Update(close_dispute(found_transaction, TransactionType::Resolve)),
+        amount: resolve_amount,
+    })
+}
 
-    // like others, we know this will succeed
-    let client = clients
-        .get_client_mut(transaction.client_id)
+fn process_chargeback<S: TransactionStore>(
+    transaction: Transaction,
+    clients: &ClientPool,
+    transaction_tree: &S,
+    policy: &EnginePolicy,
+) -> Result<Proposal> {
+    let mut client = clients
+        .get_client(transaction.client_id)
+        .copied()
         .ok_or_else(|| {
             anyhow::anyhow!(
                 "Error: Client not found in pool: {:?}",
@@ -290,78 +1181,158 @@ fn process_resolve(
             )
         })?;
 
-    // locked accounts should not continue
-    if client.locked {
+    let found_transaction = find_disputable(&transaction, transaction_tree)?;
+
+    // locked accounts should not continue, unless the policy allows the type
+    if client.locked && !policy.allows_when_locked(transaction.tx_type) {
         return Err(anyhow::anyhow!(
-            "client {:?} is locked, cannot process resolve",
+            "client {:?} is locked, cannot process chargeback",
             transaction.client_id
         ));
     }
 
-    let resolve_amount = found_transaction
+    let chargeback_amount = found_transaction
         .amount
         .ok_or_else(|| anyhow::anyhow!("Error: Transaction amount was not provided",))?;
 
-    // Since only deposits can be disputed, it only makes sense to resolve a deposit
-    if found_transaction.tx_type == TransactionType::Deposit {
-        // check to see if the client has enough held funds to process the dispute
-        if client.held < resolve_amount {
-            return Err(anyhow::anyhow!(
-                "Error: Client does not have enough held funds to resolve",
-            ));
-        }
+    // it only makes sense to chargeback what can be disputed
+    if !policy.is_disputable(found_transaction.tx_type) {
+        return Ok(Proposal::unchanged(client));
+    }
 
-        if !found_transaction.in_dispute {
-            return Err(anyhow::anyhow!(
-                "Error: Specified transaction is not in dispute",
-            ));
-        }
+    // check to see if the client has enough held funds to process the chargeback
+    if client.held < chargeback_amount {
+        return Err(anyhow::anyhow!(
+            "Error: Client does not have enough held funds to chargeback",
+        ));
+    }
 
-        // return the disputed amount to available balance from held balance
-        client.available += resolve_amount;
-        client.held -= resolve_amount;
-        // change to show the transaction is no longer disputed
-        found_transaction.in_dispute = false;
+    if !found_transaction.in_dispute {
+        return Err(anyhow::anyhow!(
+            "Error: Specified transaction is not in dispute",
+        ));
+    }
 
-        if !client.check_client_validity() {
-            // if the client is invalid after the resolve, we need to put it back
-            client.available -= resolve_amount;
-            client.held += resolve_amount;
-            found_transaction.in_dispute = true;
+    // a chargeback has to come in before the deadline after the dispute opened
+    check_window(
+        "Chargeback",
+        policy.chargeback_window_secs,
+        found_transaction.disputed_at,
+        transaction.timestamp,
+    )?;
 
-            return Err(anyhow::anyhow!("Error: Client is invalid after resolve",));
-        }
+    // this is the chargeback, client gets the money back and we subtract
+    client.held -= chargeback_amount;
+    if found_transaction.tx_type == TransactionType::Deposit {
+        client.total -= chargeback_amount;
+    } else {
+        // a withdrawal is reversed, so the held funds go back to the client
+        client.available += chargeback_amount;
     }
+    // Chargebacks do freeze the account though
+    client.locked = true;
 
-    Ok(())
+    if !client.check_client_validity() {
+        return Err(anyhow::anyhow!("Error: Client is invalid after chargeback",));
+    }
+
+    // change to show the transaction is no longer disputed
+    Ok(Proposal {
+        client,
+        record: Record::Update(close_dispute(
+            found_transaction,
+            TransactionType::Chargeback,
+        )),
+        amount: chargeback_amount,
+    })
 }
 
-fn process_chargeback(
+// Settles an authorization. Without an amount all of it is captured, a
+// smaller amount captures part of it and releases the rest to available.
+// Either way the authorization is closed.
+fn process_capture<S: TransactionStore>(
     transaction: Transaction,
-    clients: &mut ClientPool,
-    transaction_tree: &mut TransactionTree,
-) -> Result<()> {
-    let found_client = clients.has_client(&transaction.client_id)?;
+    clients: &ClientPool,
+    transaction_tree: &S,
+    policy: &EnginePolicy,
+) -> Result<Proposal> {
+    let mut client = clients
+        .get_client(transaction.client_id)
+        .copied()
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Error: Client not found in pool: {:?}",
+                transaction.client_id
+            )
+        })?;
 
-    if !found_client {
+    let authorization = find_authorization(&transaction, transaction_tree)?;
+
+    // locked accounts should not continue, unless the policy allows the type
+    if client.locked && !policy.allows_when_locked(transaction.tx_type) {
         return Err(anyhow::anyhow!(
-            "Error: Client not found in pool: {:?}",
+            "client {:?} is locked, cannot process capture",
             transaction.client_id
         ));
     }
 
-    let found_transaction = match transaction_tree.get_mut(&transaction.tx_id) {
-        Some(tx) => tx,
-        None => {
-            return Err(anyhow::anyhow!(
-                "Error: Provided transaction has not been processed"
-            ));
-        }
-    };
+    let authorized_amount = authorization
+        .amount
+        .ok_or_else(|| anyhow::anyhow!("Error: Transaction amount was not provided",))?;
+    let capture_amount = transaction.amount.unwrap_or(authorized_amount);
 
-    // like others, we know this will succeed
-    let client = clients
-        .get_client_mut(transaction.client_id)
+    if capture_amount < Decimal::from(0) {
+        return Err(anyhow::anyhow!("Error: capture amount is negative"));
+    }
+    if capture_amount > authorized_amount {
+        return Err(anyhow::anyhow!(
+            "Error: Capture amount is more than the authorized amount",
+        ));
+    }
+
+    // an authorization can only be captured until it expires
+    check_window(
+        "Capture",
+        policy.authorization_expiry_secs,
+        authorization.timestamp,
+        transaction.timestamp,
+    )?;
+
+    if client.pending < authorized_amount {
+        return Err(anyhow::anyhow!(
+            "Error: Client does not have enough pending funds to capture",
+        ));
+    }
+
+    // the captured part leaves the account, the rest goes back to available
+    client.pending -= authorized_amount;
+    client.available += authorized_amount - capture_amount;
+    client.total -= capture_amount;
+
+    if !client.check_client_validity() {
+        return Err(anyhow::anyhow!("Error: Client is invalid after capture",));
+    }
+
+    Ok(Proposal {
+        client,
+        record: Record::Update(Transaction {
+            captured: Some(capture_amount),
+            ..settle(authorization, TransactionType::Capture)
+        }),
+        amount: capture_amount,
+    })
+}
+
+// Releases an authorization back to available. This only gives the client
+// its own funds back, so it goes through on a locked account too.
+fn process_void<S: TransactionStore>(
+    transaction: Transaction,
+    clients: &ClientPool,
+    transaction_tree: &S,
+) -> Result<Proposal> {
+    let mut client = clients
+        .get_client(transaction.client_id)
+        .copied()
         .ok_or_else(|| {
             anyhow::anyhow!(
                 "Error: Client not found in pool: {:?}",
@@ -369,53 +1340,211 @@ fn process_chargeback(
             )
         })?;
 
-    // locked accounts should not continue
-    if client.locked {
+    let authorization = find_authorization(&transaction, transaction_tree)?;
+
+    let void_amount = authorization
+        .amount
+        .ok_or_else(|| anyhow::anyhow!("Error: Transaction amount was not provided",))?;
+
+    if client.pending < void_amount {
         return Err(anyhow::anyhow!(
-            "client {:?} is locked, cannot process chargeback",
-            transaction.client_id
+            "Error: Client does not have enough pending funds to void",
         ));
     }
 
-    let chargeback_amount = found_transaction
-        .amount
-        .ok_or_else(|| anyhow::anyhow!("Error: Transaction amount was not provided",))?;
+    client.pending -= void_amount;
+    client.available += void_amount;
 
-    // Since only deposits can be disputed, it only makes sense to chargeback a deposit
-    if found_transaction.tx_type == TransactionType::Deposit {
-        // check to see if the client has enough held funds to process the chargeback
-        if client.held < chargeback_amount {
+    if !client.check_client_validity() {
+        return Err(anyhow::anyhow!("Error: Client is invalid after void",));
+    }
+
+    Ok(Proposal {
+        client,
+        record: Record::Update(settle(authorization, TransactionType::Void)),
+        amount: void_amount,
+    })
+}
+
+// Looks up the open authorization a capture or void refers to
+fn find_authorization<S: TransactionStore>(
+    transaction: &Transaction,
+    transaction_tree: &S,
+) -> Result<Transaction> {
+    let authorization = match transaction_tree.get(&transaction.tx_id)? {
+        Some(tx) if tx.tx_type == TransactionType::Authorize => tx,
+        Some(_) => {
             return Err(anyhow::anyhow!(
-                "Error: Client does not have enough held funds to chargeback",
-            ));
+                "Error: Provided transaction is not an authorization"
+            ))
         }
-
-        if !found_transaction.in_dispute {
+        None => {
             return Err(anyhow::anyhow!(
-                "Error: Specified transaction is not in dispute",
-            ));
+                "Error: Provided authorization has not been processed"
+            ))
         }
+    };
 
-        // this is teh chargeback, client gets the money back and we subtract
-        client.held -= chargeback_amount;
-        client.total -= chargeback_amount;
-        // Chargebacks do freeze the account though
-        client.locked = true;
+    if let Some(failure) = authorization.failure {
+        return Err(anyhow::anyhow!(
+            "Error: Provided authorization was rejected ({}) and can't be settled",
+            failure
+        ));
+    }
+    // the funds are pending on the authorizing client's account
+    if authorization.client_id != transaction.client_id {
+        return Err(anyhow::anyhow!(
+            "Error: Provided authorization belongs to a different client"
+        ));
+    }
+    match authorization.settlement {
+        Some(TransactionType::Capture) => Err(anyhow::anyhow!(
+            "Error: Provided authorization was already captured"
+        )),
+        Some(_) => Err(anyhow::anyhow!(
+            "Error: Provided authorization was already voided"
+        )),
+        None => Ok(authorization),
+    }
+}
 
-        // change to show the transaction is no longer disputed
-        found_transaction.in_dispute = false;
+fn settle(mut authorization: Transaction, settlement: TransactionType) -> Transaction {
+    authorization.settlement = Some(settlement);
+    authorization
+}
 
-        if !client.check_client_validity() {
-            // if the client is invalid after the resolve, we need to put it back
-            client.held += chargeback_amount;
-            client.total += chargeback_amount;
-            found_transaction.in_dispute = true;
+// Voids the open authorizations that expired by `now`. Each expiry is
+// reported with the event of a `void` row for the authorization, so it shows
+// up in receipts, statements and the outbox like a void would.
+pub(crate) fn expire_authorizations<S: TransactionStore>(
+    now: Option<u64>,
+    clients: &mut ClientPool,
+    transaction_numbers: &mut S,
+    policy: &EnginePolicy,
+    mut outbox: Option<&mut Outbox>,
+) -> Result<Vec<ProcessingEvent>> {
+    let (Some(now), Some(expiry_secs)) = (now, policy.authorization_expiry_secs) else {
+        return Ok(Vec::new());
+    };
 
-            client.locked = false;
+    let mut events = Vec::new();
+    for key in clients.authorizations_before(now.saturating_sub(expiry_secs)) {
+        let (_, tx_id, client_id) = key;
+        let mut void = Transaction::new(TransactionType::Void, client_id, tx_id, None);
+        void.timestamp = Some(now);
 
-            return Err(anyhow::anyhow!("Error: Client is invalid after chargeback",));
-        }
+        let before = clients.get_client(client_id).copied();
+        let started = Instant::now();
+        let amount = match process_void(void, clients, transaction_numbers).and_then(|proposal| {
+            proposal.commit(&void, clients, transaction_numbers, outbox.as_deref_mut())
+        }) {
+            Ok(amount) => amount,
+            Err(e) if e.is::<StoreError>() => return Err(e),
+            // the row being applied has nothing to do with a bad authorization,
+            // it is left as it is and no longer expired
+            Err(e) => {
+                tracing::error!(
+                    client_id = client_id.number(),
+                    client_name = client_id.name(),
+                    tx_id = u32::from(tx_id),
+                    tx_type = %TransactionType::Authorize,
+                    outcome = "rejected",
+                    reason = %e,
+                    "authorization could not be expired"
+                );
+                clients.forget_authorization(key);
+                continue;
+            }
+        };
+        tracing::info!(
+            client_id = client_id.number(),
+            client_name = client_id.name(),
+            tx_id = u32::from(tx_id),
+            tx_type = %TransactionType::Authorize,
+            outcome = "expired",
+            %amount,
+            "authorization expired"
+        );
+
+        events.push(ProcessingEvent {
+            transaction: void,
+            amount: Some(amount),
+            before,
+            after: clients.get_client(client_id).copied(),
+            rejection: None,
+            replay: false,
+            alert: false,
+            rule_hits: Vec::new(),
+            elapsed: started.elapsed(),
+        });
     }
+    Ok(events)
+}
 
-    Ok(())
+// Looks up the transaction a dispute, resolve or chargeback refers to
+fn find_disputable<S: TransactionStore>(
+    transaction: &Transaction,
+    transaction_tree: &S,
+) -> Result<Transaction> {
+    match transaction_tree.get(&transaction.tx_id)? {
+        Some(tx) => match tx.failure {
+            // only there because the policy reserves the ids of rejected transactions
+            Some(failure) => Err(anyhow::anyhow!(
+                "Error: Provided transaction was rejected ({}) and can't be disputed",
+                failure
+            )),
+            // the funds are held on the client that made the transaction
+            None if tx.client_id != transaction.client_id => Err(anyhow::anyhow!(
+                "Error: Provided transaction belongs to a different client"
+            )),
+            None => Ok(tx),
+        },
+        // with compact retention only deposits are kept in full
+        None if transaction_tree.contains(&transaction.tx_id)? => Err(anyhow::anyhow!(
+            "Error: Provided transaction is not disputable"
+        )),
+        None => Err(anyhow::anyhow!(
+            "Error: Provided transaction has not been processed"
+        )),
+    }
+}
+
+fn close_dispute(mut transaction: Transaction, action: TransactionType) -> Transaction {
+    transaction.in_dispute = false;
+    transaction.disputed_at = None;
+    transaction.dispute_action = Some(action);
+    transaction
+}
+
+// Checks that `to` is at most `window_secs` after `from`. Without a window
+// there is nothing to check, with one both timestamps have to be there.
+fn check_window(
+    kind: &str,
+    window_secs: Option<u64>,
+    from: Option<u64>,
+    to: Option<u64>,
+) -> Result<()> {
+    let window_secs = match window_secs {
+        Some(window_secs) => window_secs,
+        None => return Ok(()),
+    };
+
+    let (from, to) = match (from, to) {
+        (Some(from), Some(to)) => (from, to),
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Error: {} window can't be checked without timestamps",
+                kind
+            ))
+        }
+    };
+
+    match to.checked_sub(from) {
+        Some(elapsed) if elapsed <= window_secs => Ok(()),
+        _ => Err(anyhow::anyhow!(
+            "Error: {} is outside of its {} second window",
+            kind,
+            window_secs
+        )),
+    }
 }
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+
+use super::Transaction;
+
+// What the reorder buffer did with a row
+#[derive(Debug, Clone, Copy)]
+pub enum Reordered {
+    // The row is next in sequence, or has no sequence number
+    Ready(Transaction),
+    // The row waited for its predecessors for longer than the window
+    Expired(Transaction),
+    // The row's sequence number was already passed, or is already waiting
+    Late(Transaction),
+}
+
+impl Reordered {
+    pub fn rejection(&self) -> Option<&'static str> {
+        match self {
+            Reordered::Ready(_) => None,
+            Reordered::Expired(_) => {
+                Some("Error: Predecessors did not arrive within the reorder window")
+            }
+            Reordered::Late(_) => {
+                Some("Error: Sequence number was already processed or is a duplicate")
+            }
+        }
+    }
+
+    pub fn transaction(&self) -> Transaction {
+        match self {
+            Reordered::Ready(tx) | Reordered::Expired(tx) | Reordered::Late(tx) => *tx,
+        }
+    }
+}
+
+// Puts rows back in sequence number order. Rows that arrive early are held
+// until every row before them has arrived. A held row that has waited for
+// more than `window` rows is given up on, the missing rows before it are
+// treated as lost and processing moves on from it.
+#[derive(Debug)]
+pub struct ReorderBuffer {
+    // `None` once `u64::MAX` was passed, every row after that is late
+    next_seq: Option<u64>,
+    window: usize,
+    // rows so far, used to age the held rows
+    arrived: usize,
+    // held rows by sequence number, with the row count when they arrived
+    pending: BTreeMap<u64, (usize, Transaction)>,
+}
+
+impl ReorderBuffer {
+    pub fn new(first_seq: u64, window: usize) -> Self {
+        Self {
+            next_seq: Some(first_seq),
+            window,
+            arrived: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    // Rows that are held right now
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    // Adds a row and returns every row that is decided because of it, in
+    // the order they should be processed
+    pub fn push(&mut self, transaction: Transaction) -> Vec<Reordered> {
+        self.arrived += 1;
+
+        let seq = match transaction.seq {
+            Some(seq) => seq,
+            None => return vec![Reordered::Ready(transaction)],
+        };
+
+        let passed = self.next_seq.is_none_or(|next_seq| seq < next_seq);
+        if passed || self.pending.contains_key(&seq) {
+            return vec![Reordered::Late(transaction)];
+        }
+
+        self.pending.insert(seq, (self.arrived, transaction));
+
+        let mut decided = self.release();
+        self.expire(&mut decided);
+        decided
+    }
+
+    // Called at the end of the input, anything still held never got its
+    // predecessors
+    pub fn finish(&mut self) -> Vec<Reordered> {
+        std::mem::take(&mut self.pending)
+            .into_values()
+            .map(|(_, transaction)| Reordered::Expired(transaction))
+            .collect()
+    }
+
+    fn release(&mut self) -> Vec<Reordered> {
+        let mut ready = Vec::new();
+        while let Some(next_seq) = self.next_seq {
+            let Some((_, transaction)) = self.pending.remove(&next_seq) else {
+                break;
+            };
+            ready.push(Reordered::Ready(transaction));
+            self.next_seq = next_seq.checked_add(1);
+        }
+        ready
+    }
+
+    // Every held row ages, not only the lowest one, so a row that arrived
+    // long before the rows under it isn't held past the window because of
+    // them
+    fn expire(&mut self, decided: &mut Vec<Reordered>) {
+        let expired = self
+            .pending
+            .iter()
+            .filter(|(_, (arrived_at, _))| self.arrived - arrived_at > self.window)
+            .map(|(seq, _)| *seq)
+            .max();
+        let Some(seq) = expired else {
+            return;
+        };
+
+        // give up on the gap before this row, the rows held in that gap and
+        // the row itself
+        let later = match seq.checked_add(1) {
+            Some(next_seq) => self.pending.split_off(&next_seq),
+            None => BTreeMap::new(),
+        };
+        let given_up = std::mem::replace(&mut self.pending, later);
+        decided.extend(
+            given_up
+                .into_values()
+                .map(|(_, transaction)| Reordered::Expired(transaction)),
+        );
+        self.next_seq = seq.checked_add(1);
+        decided.extend(self.release());
+    }
+}
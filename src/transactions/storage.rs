@@ -0,0 +1,481 @@
+use anyhow::Result;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::{
+    management::TransactionTree, FailureReason, Transaction, TransactionId, TransactionType,
+};
+use crate::clients::ClientId;
+
+// The operations processing needs from wherever the processed transactions
+// are kept. `TransactionTree` keeps them in memory, `DiskStore` keeps them
+// in files so the history can be larger than memory.
+pub trait TransactionStore {
+    fn insert(&mut self, transaction: Transaction) -> Result<()>;
+
+    fn contains(&self, tx_id: &TransactionId) -> Result<bool>;
+
+    // Returns None for missing transactions, and for transactions that are
+    // only kept by id
+    fn get(&self, tx_id: &TransactionId) -> Result<Option<Transaction>>;
+
+    // Replaces the stored state of an existing transaction, returns false
+    // if the transaction does not exist
+    fn update(&mut self, transaction: Transaction) -> Result<bool>;
+
+    // Returns false if the transaction does not exist
+    fn set_in_dispute(&mut self, tx_id: &TransactionId, in_dispute: bool) -> Result<bool> {
+        match self.get(tx_id)? {
+            Some(mut transaction) => {
+                transaction.in_dispute = in_dispute;
+                self.update(transaction)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+// Wraps errors that come from the store itself (as opposed to a transaction
+// being invalid), processing stops on these instead of skipping the row.
+#[derive(Debug)]
+pub struct StoreError(pub io::Error);
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "transaction store failure: {}", self.0)
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        Self(e)
+    }
+}
+
+impl TransactionStore for TransactionTree {
+    fn insert(&mut self, transaction: Transaction) -> Result<()> {
+        TransactionTree::insert(self, transaction);
+        Ok(())
+    }
+
+    fn contains(&self, tx_id: &TransactionId) -> Result<bool> {
+        Ok(TransactionTree::contains(self, tx_id))
+    }
+
+    fn get(&self, tx_id: &TransactionId) -> Result<Option<Transaction>> {
+        Ok(TransactionTree::get(self, tx_id))
+    }
+
+    fn update(&mut self, transaction: Transaction) -> Result<bool> {
+        Ok(TransactionTree::update(self, transaction))
+    }
+
+    fn set_in_dispute(&mut self, tx_id: &TransactionId, in_dispute: bool) -> Result<bool> {
+        Ok(TransactionTree::set_in_dispute(self, tx_id, in_dispute))
+    }
+}
+
+// Keeps the writes meant for another store to itself, so the rows of a batch
+// can be tried out and then committed together or dropped. Reads see the
+// staged writes first.
+pub(crate) struct StagedStore<'a, S> {
+    base: &'a S,
+    // true for transactions that are new, false for updates
+    writes: BTreeMap<TransactionId, (bool, Transaction)>,
+}
+
+impl<'a, S: TransactionStore> StagedStore<'a, S> {
+    pub(crate) fn new(base: &'a S) -> Self {
+        Self {
+            base,
+            writes: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn into_writes(self) -> StagedWrites {
+        StagedWrites(self.writes)
+    }
+}
+
+impl<S: TransactionStore> TransactionStore for StagedStore<'_, S> {
+    fn insert(&mut self, transaction: Transaction) -> Result<()> {
+        self.writes.insert(transaction.tx_id, (true, transaction));
+        Ok(())
+    }
+
+    fn contains(&self, tx_id: &TransactionId) -> Result<bool> {
+        Ok(self.writes.contains_key(tx_id) || self.base.contains(tx_id)?)
+    }
+
+    fn get(&self, tx_id: &TransactionId) -> Result<Option<Transaction>> {
+        match self.writes.get(tx_id) {
+            Some((_, transaction)) => Ok(Some(*transaction)),
+            None => self.base.get(tx_id),
+        }
+    }
+
+    fn update(&mut self, transaction: Transaction) -> Result<bool> {
+        let is_new = match self.writes.get(&transaction.tx_id) {
+            Some((is_new, _)) => *is_new,
+            None if self.base.contains(&transaction.tx_id)? => false,
+            None => return Ok(false),
+        };
+        self.writes.insert(transaction.tx_id, (is_new, transaction));
+        Ok(true)
+    }
+}
+
+// What a `StagedStore` would have written
+pub(crate) struct StagedWrites(BTreeMap<TransactionId, (bool, Transaction)>);
+
+impl StagedWrites {
+    pub(crate) fn commit<S: TransactionStore>(self, store: &mut S) -> Result<()> {
+        for (is_new, transaction) in self.0.into_values() {
+            if is_new {
+                store.insert(transaction)?;
+            } else {
+                store.update(transaction)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+const RECORD_SIZE: usize = 62;
+const INDEX_ENTRY_SIZE: u64 = 8;
+// bumped whenever the record layout changes, stores written with another
+// layout can't be read
+const FORMAT_VERSION: u32 = 3;
+
+const FLAG_IN_DISPUTE: u8 = 0b01;
+const FLAG_HAS_AMOUNT: u8 = 0b10;
+const FLAG_HAS_TIMESTAMP: u8 = 0b100;
+const FLAG_HAS_DISPUTED_AT: u8 = 0b1000;
+// the top 4 bits of the flags hold the dispute action, as a type code plus one
+const DISPUTE_ACTION_SHIFT: u8 = 4;
+// the type only needs the low bits of its byte, the top 4 bits hold the
+// failure reason of a rejected transaction as a code plus one
+const TYPE_MASK: u8 = 0b1111;
+const FAILURE_SHIFT: u8 = 4;
+
+// Keeps transactions on disk in two files inside a directory:
+//
+// - `transactions.dat` is append-only, every insert or dispute state change
+//   appends a fixed size record.
+// - `transactions.idx` has one 8 byte slot per possible TransactionId with
+//   the offset of the latest record plus one (zero means no record). It is
+//   written sparsely, so only the pages for ids that are used take space.
+// - `transactions.version` has the version of the record layout.
+//
+// Nothing is held in memory except the open files, so the history is only
+// limited by disk space.
+#[derive(Debug)]
+pub struct DiskStore {
+    data: File,
+    index: File,
+    data_len: u64,
+}
+
+impl DiskStore {
+    // Opens the store in the directory, creating it if needed. Transactions
+    // from a previous run in the same directory are kept, but nothing else
+    // from that run is, such as the clients they belong to.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+
+        let options = {
+            let mut options = OpenOptions::new();
+            options.read(true).write(true).create(true);
+            options
+        };
+        let data = options.open(dir.join("transactions.dat"))?;
+        let index = options.open(dir.join("transactions.idx"))?;
+
+        let data_len = data.metadata()?.len();
+        check_version(dir, data_len)?;
+        if data_len % RECORD_SIZE as u64 != 0 {
+            return Err(anyhow::anyhow!(
+                "transaction store data file is corrupt, length {} is not a multiple of {}",
+                data_len,
+                RECORD_SIZE
+            ));
+        }
+
+        Ok(Self {
+            data,
+            index,
+            data_len,
+        })
+    }
+
+    // Whether no transaction was ever written to the store
+    pub fn is_empty(&self) -> bool {
+        self.data_len == 0
+    }
+
+    fn read_index(&self, tx_id: &TransactionId) -> Result<Option<u64>, StoreError> {
+        let mut slot = [0u8; INDEX_ENTRY_SIZE as usize];
+        let mut index = &self.index;
+        index.seek(SeekFrom::Start(
+            u64::from(u32::from(*tx_id)) * INDEX_ENTRY_SIZE,
+        ))?;
+
+        // reading past the end of the sparse file means the slot was never written
+        match index.read_exact(&mut slot) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        match u64::from_le_bytes(slot) {
+            0 => Ok(None),
+            offset => Ok(Some(offset - 1)),
+        }
+    }
+
+    fn read_record(&self, offset: u64) -> Result<Transaction, StoreError> {
+        let mut record = [0u8; RECORD_SIZE];
+        let mut data = &self.data;
+        data.seek(SeekFrom::Start(offset))?;
+        data.read_exact(&mut record)?;
+        decode_record(&record)
+            .map_err(|e| StoreError(io::Error::new(io::ErrorKind::InvalidData, e)))
+    }
+
+    fn append(&mut self, transaction: &Transaction) -> Result<(), StoreError> {
+        // records only have room for numeric client ids, names would need
+        // a table of their own next to the records
+        let client_id = transaction.client_id.number().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the disk store only holds numeric client ids, not '{}'",
+                    transaction.client_id
+                ),
+            )
+        })?;
+        let offset = self.data_len;
+
+        self.data.seek(SeekFrom::Start(offset))?;
+        self.data
+            .write_all(&encode_record(transaction, client_id))?;
+        self.data_len += RECORD_SIZE as u64;
+
+        // the index is only updated once the record is fully written
+        self.index.seek(SeekFrom::Start(
+            u64::from(u32::from(transaction.tx_id)) * INDEX_ENTRY_SIZE,
+        ))?;
+        self.index.write_all(&(offset + 1).to_le_bytes())?;
+
+        Ok(())
+    }
+}
+
+// A new store gets the current version. A store that already has records
+// has to be of the current version, stores from before versions were written
+// have none.
+fn check_version(dir: &Path, data_len: u64) -> Result<()> {
+    let filename = dir.join("transactions.version");
+    let version = match std::fs::read_to_string(&filename) {
+        Ok(text) => Some(text.trim().parse::<u32>().map_err(|_| {
+            anyhow::anyhow!("transaction store version file is corrupt: {:?}", text)
+        })?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    match version {
+        Some(FORMAT_VERSION) => Ok(()),
+        None if data_len == 0 => {
+            std::fs::write(&filename, format!("{}\n", FORMAT_VERSION))?;
+            Ok(())
+        }
+        version => Err(anyhow::anyhow!(
+            "transaction store has version {}, this build only reads version {}",
+            version.map_or(String::from("1"), |version| version.to_string()),
+            FORMAT_VERSION
+        )),
+    }
+}
+
+impl TransactionStore for DiskStore {
+    fn insert(&mut self, transaction: Transaction) -> Result<()> {
+        Ok(self.append(&transaction)?)
+    }
+
+    fn contains(&self, tx_id: &TransactionId) -> Result<bool> {
+        Ok(self.read_index(tx_id)?.is_some())
+    }
+
+    fn get(&self, tx_id: &TransactionId) -> Result<Option<Transaction>> {
+        match self.read_index(tx_id)? {
+            Some(offset) => Ok(Some(self.read_record(offset)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn update(&mut self, transaction: Transaction) -> Result<bool> {
+        if self.read_index(&transaction.tx_id)?.is_none() {
+            return Ok(false);
+        }
+        self.append(&transaction)?;
+        Ok(true)
+    }
+}
+
+// Record layout, little endian:
+// tx id (4) | client id (8) | type and failure (1) | flags (1) | amount (16) |
+// timestamp (8) | disputed at (8) | captured (16)
+//
+// Authorizations can't be disputed, so the disputed at bytes hold their
+// settlement instead, as a type code plus one. Captured is only set for
+// captured authorizations.
+fn encode_record(transaction: &Transaction, client_id: u64) -> [u8; RECORD_SIZE] {
+    let mut record = [0u8; RECORD_SIZE];
+
+    record[0..4].copy_from_slice(&u32::from(transaction.tx_id).to_le_bytes());
+    record[4..12].copy_from_slice(&client_id.to_le_bytes());
+    record[12] = encode_type(transaction.tx_type);
+    if let Some(failure) = transaction.failure {
+        record[12] |= (encode_failure(failure) + 1) << FAILURE_SHIFT;
+    }
+
+    let mut flags = 0;
+    if transaction.in_dispute {
+        flags |= FLAG_IN_DISPUTE;
+    }
+    if let Some(amount) = transaction.amount {
+        flags |= FLAG_HAS_AMOUNT;
+        record[14..30].copy_from_slice(&amount.serialize());
+    }
+    if let Some(timestamp) = transaction.timestamp {
+        flags |= FLAG_HAS_TIMESTAMP;
+        record[30..38].copy_from_slice(&timestamp.to_le_bytes());
+    }
+    if let Some(disputed_at) = transaction.disputed_at {
+        flags |= FLAG_HAS_DISPUTED_AT;
+        record[38..46].copy_from_slice(&disputed_at.to_le_bytes());
+    }
+    if let Some(settlement) = transaction.settlement {
+        record[38] = encode_type(settlement) + 1;
+    }
+    if let Some(captured) = transaction.captured {
+        record[46..62].copy_from_slice(&captured.serialize());
+    }
+    if let Some(action) = transaction.dispute_action {
+        flags |= (encode_type(action) + 1) << DISPUTE_ACTION_SHIFT;
+    }
+    record[13] = flags;
+
+    record
+}
+
+fn encode_type(tx_type: TransactionType) -> u8 {
+    match tx_type {
+        TransactionType::Deposit => 0,
+        TransactionType::Withdrawal => 1,
+        TransactionType::Dispute => 2,
+        TransactionType::Resolve => 3,
+        TransactionType::Chargeback => 4,
+        TransactionType::Authorize => 5,
+        TransactionType::Capture => 6,
+        TransactionType::Void => 7,
+    }
+}
+
+fn decode_type(code: u8) -> Result<TransactionType, String> {
+    match code {
+        0 => Ok(TransactionType::Deposit),
+        1 => Ok(TransactionType::Withdrawal),
+        2 => Ok(TransactionType::Dispute),
+        3 => Ok(TransactionType::Resolve),
+        4 => Ok(TransactionType::Chargeback),
+        5 => Ok(TransactionType::Authorize),
+        6 => Ok(TransactionType::Capture),
+        7 => Ok(TransactionType::Void),
+        other => Err(format!("unknown transaction type {} in record", other)),
+    }
+}
+
+fn encode_failure(failure: FailureReason) -> u8 {
+    match failure {
+        FailureReason::AccountLocked => 0,
+        FailureReason::MissingAmount => 1,
+        FailureReason::NegativeAmount => 2,
+        FailureReason::InsufficientFunds => 3,
+        FailureReason::InvalidClient => 4,
+        FailureReason::Other => 5,
+        FailureReason::RiskRule => 6,
+    }
+}
+
+fn decode_failure(code: u8) -> Result<FailureReason, String> {
+    match code {
+        0 => Ok(FailureReason::AccountLocked),
+        1 => Ok(FailureReason::MissingAmount),
+        2 => Ok(FailureReason::NegativeAmount),
+        3 => Ok(FailureReason::InsufficientFunds),
+        4 => Ok(FailureReason::InvalidClient),
+        5 => Ok(FailureReason::Other),
+        6 => Ok(FailureReason::RiskRule),
+        other => Err(format!("unknown failure reason {} in record", other)),
+    }
+}
+
+fn decode_record(record: &[u8; RECORD_SIZE]) -> Result<Transaction, String> {
+    let tx_type = decode_type(record[12] & TYPE_MASK)?;
+    let failure = match record[12] >> FAILURE_SHIFT {
+        0 => None,
+        code => Some(decode_failure(code - 1)?),
+    };
+
+    let flags = record[13];
+    let dispute_action = match flags >> DISPUTE_ACTION_SHIFT {
+        0 => None,
+        code => Some(decode_type(code - 1)?),
+    };
+    let read_decimal = |start: usize| {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&record[start..start + 16]);
+        Decimal::deserialize(bytes)
+    };
+    let amount = (flags & FLAG_HAS_AMOUNT != 0).then(|| read_decimal(14));
+
+    let settlement = match record[38] {
+        code if tx_type == TransactionType::Authorize && code != 0 => Some(decode_type(code - 1)?),
+        _ => None,
+    };
+    let captured = (settlement == Some(TransactionType::Capture)).then(|| read_decimal(46));
+
+    let read_u64 = |start: usize| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&record[start..start + 8]);
+        u64::from_le_bytes(bytes)
+    };
+    let read_time = |flag: u8, start: usize| (flags & flag != 0).then(|| read_u64(start));
+
+    Ok(Transaction {
+        tx_type,
+        client_id: ClientId::from_u64(read_u64(4)),
+        tx_id: TransactionId::from(u32::from_le_bytes([
+            record[0], record[1], record[2], record[3],
+        ])),
+        amount,
+        in_dispute: flags & FLAG_IN_DISPUTE != 0,
+        timestamp: read_time(FLAG_HAS_TIMESTAMP, 30),
+        disputed_at: read_time(FLAG_HAS_DISPUTED_AT, 38),
+        dispute_action,
+        failure,
+        settlement,
+        captured,
+        seq: None,
+        batch: None,
+    })
+}
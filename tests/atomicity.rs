@@ -0,0 +1,85 @@
+use rust_decimal::Decimal;
+use tps::clients::ClientId;
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::risk::{RiskRule, RuleAction, RuleKind};
+use tps::transactions::processing::TransactionError;
+use tps::transactions::{Transaction, TransactionId, TransactionType};
+
+fn transaction(tx_type: TransactionType, client_id: u16, tx_id: u32, amount: i64) -> Transaction {
+    Transaction::new(
+        tx_type,
+        ClientId::from(client_id),
+        TransactionId::from(tx_id),
+        (amount != 0).then(|| Decimal::new(amount, 1)),
+    )
+}
+
+#[cfg(test)]
+#[test]
+fn rejected_transactions_write_nothing() {
+    let mut engine = Engine::new(EnginePolicy::default());
+    engine
+        .apply(transaction(TransactionType::Deposit, 1, 1, 100))
+        .unwrap();
+    engine
+        .apply(transaction(TransactionType::Withdrawal, 1, 2, 80))
+        .unwrap();
+
+    // not enough available to hold for the dispute, and nothing to charge back
+    for tx_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+        assert!(matches!(
+            engine.apply(transaction(tx_type, 1, 1, 0)),
+            Err(TransactionError::Rejected { .. })
+        ));
+    }
+
+    let client = engine.clients().get_client(ClientId::from(1)).unwrap();
+    assert_eq!(client.available, Decimal::new(20, 1));
+    assert_eq!(client.held, Decimal::ZERO);
+    assert!(!client.locked);
+
+    let deposit = engine.transactions().get(&TransactionId::from(1)).unwrap();
+    assert!(!deposit.in_dispute);
+    assert_eq!(deposit.dispute_action, None);
+}
+
+#[cfg(test)]
+#[test]
+fn rejected_rows_only_create_clients_when_the_policy_says_so() {
+    let risk_rule = RiskRule {
+        name: String::from("large"),
+        kind: RuleKind::MaxAmount,
+        tx_type: None,
+        limit: Decimal::new(1000, 0),
+        last: None,
+        window_secs: None,
+        action: RuleAction::Reject,
+        tier: None,
+    };
+    let policy = EnginePolicy {
+        risk_rules: vec![risk_rule],
+        ..EnginePolicy::default()
+    };
+
+    let mut engine = Engine::new(policy.clone());
+    // a withdrawal without funds still creates the client by default
+    assert!(engine
+        .apply(transaction(TransactionType::Withdrawal, 1, 1, 10))
+        .is_err());
+    // the risk rules stop a row before it gets to create its client
+    assert!(engine
+        .apply(transaction(TransactionType::Deposit, 2, 2, 20000))
+        .is_err());
+    assert!(engine.clients().get_client(ClientId::from(1)).is_some());
+    assert!(engine.clients().get_client(ClientId::from(2)).is_none());
+
+    let mut engine = Engine::new(EnginePolicy {
+        create_clients_on_rejection: false,
+        ..policy
+    });
+    assert!(engine
+        .apply(transaction(TransactionType::Withdrawal, 1, 1, 10))
+        .is_err());
+    assert!(engine.clients().get_client(ClientId::from(1)).is_none());
+}
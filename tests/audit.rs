@@ -0,0 +1,124 @@
+mod common;
+
+use common::process_file;
+use rust_decimal::Decimal;
+use tps::audit::{audit, AuditDiscrepancy, Ledger};
+use tps::clients::{ClientId, ClientPool};
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::transactions::management::TransactionTree;
+
+fn run(input_csv_filename: &str, policy: &EnginePolicy) -> (Ledger, ClientPool, TransactionTree) {
+    let mut engine = Engine::new(policy.clone());
+    let mut ledger = Ledger::new();
+
+    for event in process_file(&mut engine, input_csv_filename) {
+        ledger.record(&event);
+    }
+    let (client_pool, transations) = engine.into_parts();
+
+    (ledger, client_pool, transations)
+}
+
+#[cfg(test)]
+#[test]
+fn audit_balances() {
+    let (ledger, client_pool, transations) =
+        run("tests/t13_transactions.csv", &EnginePolicy::default());
+    let report = audit(&ledger, &client_pool, &transations);
+
+    assert!(report.is_clean(), "{}", report);
+    assert_eq!(report.lines.len(), 3);
+
+    // rejected rows on the locked account aren't counted, the chargeback is
+    let line = report.lines[1];
+    assert_eq!(line.flows.deposits, Decimal::new(8, 0));
+    assert_eq!(line.flows.withdrawals, Decimal::ZERO);
+    assert_eq!(line.flows.chargebacks, Decimal::new(8, 0));
+    assert_eq!(line.total, Decimal::ZERO);
+
+    // the open dispute is held
+    assert_eq!(report.lines[0].held, Decimal::new(5, 0));
+    assert_eq!(report.lines[0].disputed, Decimal::new(5, 0));
+
+    let (flows, total, held, _) = report.totals();
+    assert_eq!(flows.expected_total(), total);
+    assert_eq!(total, Decimal::new(14, 0));
+    assert_eq!(held, Decimal::new(5, 0));
+
+    // a charged back withdrawal gives the amount back, replays aren't counted
+    for (input_csv_filename, policy) in [
+        (
+            "tests/t12_transactions.csv",
+            EnginePolicy::from_toml_file("tests/t12_policy.toml").unwrap(),
+        ),
+        ("tests/t8_transactions.csv", EnginePolicy::default()),
+    ] {
+        let (ledger, client_pool, transations) = run(input_csv_filename, &policy);
+        let report = audit(&ledger, &client_pool, &transations);
+        assert!(report.is_clean(), "{}", report);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn audit_discrepancies() {
+    let (ledger, mut client_pool, transations) =
+        run("tests/t13_transactions.csv", &EnginePolicy::default());
+
+    let client = client_pool.get_client_mut(ClientId::from(1)).unwrap();
+    client.held -= Decimal::new(1, 0);
+    client.total -= Decimal::new(1, 0);
+    let client = client_pool.get_client_mut(ClientId::from(3)).unwrap();
+    client.total += Decimal::new(5, 1);
+
+    let report = audit(&ledger, &client_pool, &transations);
+    assert_eq!(
+        report.discrepancies,
+        vec![
+            AuditDiscrepancy::Total {
+                client_id: ClientId::from(1),
+                expected: Decimal::new(12, 0),
+                actual: Decimal::new(11, 0),
+            },
+            AuditDiscrepancy::Held {
+                client_id: ClientId::from(1),
+                expected: Decimal::new(5, 0),
+                actual: Decimal::new(4, 0),
+            },
+            AuditDiscrepancy::Total {
+                client_id: ClientId::from(3),
+                expected: Decimal::new(2, 0),
+                actual: Decimal::new(25, 1),
+            },
+            AuditDiscrepancy::Ledger {
+                expected: Decimal::new(14, 0),
+                actual: Decimal::new(135, 1),
+            },
+        ]
+    );
+
+    let printed = report.to_string();
+    assert!(printed.contains("audit: 3 clients, 4 discrepancies"));
+    assert!(printed.contains("client 1: total expected 12.0000, actual 11.0000, delta -1.0000"));
+}
+
+#[cfg(test)]
+#[test]
+fn audit_open_withdrawal_disputes() {
+    let policy = EnginePolicy::from_toml_file("tests/t12_policy.toml").unwrap();
+    let (ledger, client_pool, transations) = run("tests/t19_transactions.csv", &policy);
+    let report = audit(&ledger, &client_pool, &transations);
+    assert!(report.is_clean(), "{}", report);
+
+    // the open dispute credits the withdrawal back until it is settled
+    let line = report.lines[0];
+    assert_eq!(line.flows.disputed_withdrawals, Decimal::new(3, 0));
+    assert_eq!(line.flows.expected_total(), Decimal::new(10, 0));
+    assert_eq!(line.held, Decimal::new(3, 0));
+
+    // a resolved one is released again
+    let line = report.lines[1];
+    assert_eq!(line.flows.disputed_withdrawals, Decimal::ZERO);
+    assert_eq!(line.total, Decimal::new(3, 0));
+}
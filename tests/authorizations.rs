@@ -0,0 +1,214 @@
+use rust_decimal::Decimal;
+use tps::audit::{audit, Ledger};
+use tps::clients::ClientId;
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::read_buffer_to_csv;
+use tps::transactions::processing::Outcome;
+use tps::transactions::{Transaction, TransactionId, TransactionType};
+
+// (tx, type, amount, rejection) of an event
+type EventSummary = (u32, TransactionType, Option<Decimal>, Option<String>);
+
+// Runs the file and returns the engine, the ledger and a summary of every
+// event
+fn run(policy: EnginePolicy) -> (Engine, Ledger, Vec<EventSummary>) {
+    let csv_content = read_buffer_to_csv("tests/t17_transactions.csv").unwrap();
+    let mut engine = Engine::new(policy);
+    let mut ledger = Ledger::new();
+    let mut events = Vec::new();
+    engine
+        .apply_batch_with_events(csv_content, |event| {
+            ledger.record(event);
+            events.push((
+                u32::from(event.transaction.tx_id),
+                event.transaction.tx_type,
+                event.amount,
+                event.rejection.clone(),
+            ));
+        })
+        .unwrap();
+    (engine, ledger, events)
+}
+
+#[cfg(test)]
+#[test]
+fn authorize_capture_and_void() {
+    // client 2 still has 5.0 authorized, so the pending column is printed
+    let expected_result = r#"client, available, held, pending, total, locked
+1, 75.0000, 0.0000, 0.0000, 75.0000, false
+2, 45.0000, 0.0000, 5.0000, 50.0000, false
+3, 0.0000, 0.0000, 0.0000, 0.0000, false
+4, 1.0000, 0.0000, 0.0000, 1.0000, false
+"#;
+
+    let (engine, ledger, events) = run(EnginePolicy::default());
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+
+    let rejection = |index: usize| events[index].3.as_deref();
+    // a partial capture moves only the captured part out of the account
+    assert_eq!(events[2].2, Some(Decimal::new(250, 1)));
+    assert_eq!(
+        rejection(5),
+        Some("Error: Client does not have enough available balance to authorize")
+    );
+    // the repeated capture is a replay, not a second capture
+    assert_eq!(rejection(6), None);
+    assert_eq!(events[6].2, None);
+    // pending funds can't be disputed, they aren't available
+    assert_eq!(
+        rejection(9),
+        Some("Error: Client does not have enough available balance to dispute")
+    );
+    assert_eq!(
+        rejection(12),
+        Some("Error: Provided authorization belongs to a different client")
+    );
+    // a capture without an amount takes the whole authorization
+    assert_eq!(events[16].2, Some(Decimal::new(100, 1)));
+
+    // authorizations don't touch the dispute state
+    let authorization = engine.transactions().get(&9.into()).unwrap();
+    assert_eq!(authorization.settlement, Some(TransactionType::Capture));
+    assert!(!authorization.in_dispute);
+    assert_eq!(authorization.dispute_action, None);
+
+    let report = audit(&ledger, engine.clients(), engine.transactions());
+    assert!(report.is_clean(), "{}", report);
+}
+
+#[cfg(test)]
+#[test]
+fn expired_authorizations_are_voided() {
+    let expected_result = r#"client, available, held, total, locked
+1, 75.0000, 0.0000, 75.0000, false
+2, 50.0000, 0.0000, 50.0000, false
+3, 10.0000, 0.0000, 10.0000, false
+4, 1.0000, 0.0000, 1.0000, false
+"#;
+
+    let policy = EnginePolicy {
+        authorization_expiry_secs: Some(3600),
+        ..EnginePolicy::default()
+    };
+    let (engine, ledger, events) = run(policy);
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+
+    // the row at 6000 is the first one past the expiry of the authorizations
+    // made at 1600 and 2000, they are voided before it
+    assert_eq!(events.len(), 19);
+    assert_eq!(
+        events[15..18],
+        [
+            (7, TransactionType::Void, Some(Decimal::new(50, 1)), None),
+            (9, TransactionType::Void, Some(Decimal::new(100, 1)), None),
+            (
+                10,
+                TransactionType::Deposit,
+                Some(Decimal::new(10, 1)),
+                None
+            ),
+        ]
+    );
+    assert_eq!(
+        events[18].3.as_deref(),
+        Some("Error: Provided authorization was already voided")
+    );
+    assert_eq!(
+        engine
+            .clients()
+            .get_client(ClientId::from(3))
+            .unwrap()
+            .pending,
+        Decimal::ZERO
+    );
+
+    let report = audit(&ledger, engine.clients(), engine.transactions());
+    assert!(report.is_clean(), "{}", report);
+}
+
+#[cfg(test)]
+#[test]
+fn state_columns_in_the_input_are_ignored() {
+    // the rows set the engine's own state columns, the deposit as already
+    // disputed and the authorization as already captured
+    let expected_result = r#"client, available, held, total, locked
+1, 6.0000, 0.0000, 6.0000, false
+"#;
+
+    let csv_content = read_buffer_to_csv("tests/t18_transactions.csv").unwrap();
+    let mut engine = Engine::new(EnginePolicy::default());
+    let mut events = Vec::new();
+    engine
+        .apply_batch_with_events(csv_content, |event| {
+            events.push((event.amount, event.replay, event.rejection.clone()))
+        })
+        .unwrap();
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+
+    // the dispute and the capture are applied, not taken as replays
+    assert_eq!(events[1], (Some(Decimal::new(100, 1)), false, None));
+    assert_eq!(events[4], (Some(Decimal::new(40, 1)), false, None));
+}
+
+#[cfg(test)]
+#[test]
+fn bad_authorizations_dont_stop_later_rows() {
+    let policy = EnginePolicy {
+        authorization_expiry_secs: Some(3600),
+        ..EnginePolicy::default()
+    };
+    let row = |tx_type, tx_id: u32, amount, timestamp| {
+        let mut transaction = Transaction::new(
+            tx_type,
+            ClientId::from(1),
+            TransactionId::from(tx_id),
+            Some(Decimal::new(amount, 0)),
+        );
+        transaction.timestamp = Some(timestamp);
+        transaction
+    };
+
+    let mut engine = Engine::new(policy.clone());
+    engine
+        .apply(row(TransactionType::Deposit, 1, 10, 1000))
+        .unwrap();
+    engine
+        .apply(row(TransactionType::Authorize, 2, 4, 1000))
+        .unwrap();
+
+    // the pending funds are gone, so the authorization can't be voided
+    let (mut clients, store) = engine.into_parts();
+    let client = clients.get_client_mut(ClientId::from(1)).unwrap();
+    client.pending = Decimal::ZERO;
+    client.total = client.available;
+    let mut engine = Engine::with_state(clients, store, policy);
+
+    // the deposit after the expiry still goes through, and the authorization
+    // is left open
+    assert!(matches!(
+        engine.apply(row(TransactionType::Deposit, 3, 5, 9000)),
+        Ok(Outcome::Applied(_))
+    ));
+    assert_eq!(
+        engine
+            .clients()
+            .get_client(ClientId::from(1))
+            .unwrap()
+            .available,
+        Decimal::new(11, 0)
+    );
+    assert_eq!(
+        engine.transactions().get(&2.into()).unwrap().settlement,
+        None
+    );
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use common::process_file;
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+
+#[cfg(test)]
+#[test]
+fn batches_are_applied_all_or_nothing() {
+    // batch 8 fails on its withdrawal, so client 3's deposit is rolled back
+    // with it and the id is free again
+    let expected_result = r#"client, available, held, total, locked
+1, 70.0000, 0.0000, 70.0000, false
+2, 45.0000, 0.0000, 45.0000, false
+3, 1.0000, 5.0000, 6.0000, false
+"#;
+
+    let mut engine = Engine::new(EnginePolicy::default());
+    process_file(&mut engine, "tests/t14_transactions.csv");
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+
+    // a rejected batch doesn't reserve its ids either
+    let policy = EnginePolicy {
+        reserve_failed_ids: true,
+        ..EnginePolicy::default()
+    };
+    let mut engine = Engine::new(policy);
+    process_file(&mut engine, "tests/t14_transactions.csv");
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn every_row_of_a_rejected_batch_gets_the_batch_reason() {
+    let mut engine = Engine::new(EnginePolicy::default());
+    let events = process_file(&mut engine, "tests/t14_transactions.csv");
+    assert_eq!(events.len(), 10);
+
+    let rejected: Vec<u32> = events
+        .iter()
+        .filter(|event| !event.is_accepted())
+        .map(|event| event.transaction.tx_id.into())
+        .collect();
+    assert_eq!(rejected, vec![5, 6]);
+
+    for event in events.iter().filter(|event| !event.is_accepted()) {
+        assert!(event
+            .rejection
+            .as_deref()
+            .unwrap()
+            .starts_with("Error: Batch 8 rejected, transaction 6 failed"));
+        assert_eq!(event.amount, None);
+    }
+
+    // the deposit in the rejected batch never created client 3
+    let rolled_back = &events[4];
+    assert!(rolled_back.before.is_none() && rolled_back.after.is_none());
+
+    // committed rows keep their own before and after state
+    let staged_dispute = &events[8];
+    assert!(staged_dispute.is_accepted());
+    let after = staged_dispute.after.unwrap();
+    assert_eq!(after.held, after.total);
+}
+
+#[cfg(test)]
+#[test]
+fn batch_ids_that_come_back_are_rejected() {
+    let expected_result = r#"client, available, held, total, locked
+1, 17.0000, 0.0000, 17.0000, false
+"#;
+
+    let mut engine = Engine::new(EnginePolicy::default());
+    let events = process_file(&mut engine, "tests/t20_transactions.csv");
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+
+    // batch 3 was already applied when its id comes back
+    let rejected: Vec<(u32, &str)> = events
+        .iter()
+        .filter(|event| !event.is_accepted())
+        .map(|event| {
+            (
+                event.transaction.tx_id.into(),
+                event.rejection.as_deref().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        rejected,
+        vec![
+            (
+                3,
+                "Error: Batch id was already used by an earlier run of rows"
+            ),
+            (
+                5,
+                "Error: Batch id was already used by an earlier run of rows"
+            ),
+        ]
+    );
+}
@@ -0,0 +1,114 @@
+use rust_decimal::Decimal;
+use tps::clients::{ClientId, ClientIdKind};
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::read_transactions;
+use tps::transactions::processing::TransactionError;
+use tps::transactions::{Transaction, TransactionId, TransactionType};
+
+// Writes a one row input with the given client id
+fn single_row_file(name: &str, client: &str) -> std::path::PathBuf {
+    let filename = std::env::temp_dir().join(format!(
+        "tps-client-ids-{}-{}.csv",
+        name,
+        std::process::id()
+    ));
+    std::fs::write(
+        &filename,
+        format!("type, client, tx, amount\ndeposit, {}, 1, 1.0\n", client),
+    )
+    .unwrap();
+    filename
+}
+
+#[cfg(test)]
+#[test]
+fn string_client_ids() {
+    let csv_content =
+        read_transactions("tests/t15_transactions.csv", ClientIdKind::String).unwrap();
+
+    let mut engine = Engine::new(EnginePolicy {
+        client_ids: ClientIdKind::String,
+        ..EnginePolicy::default()
+    });
+    engine.apply_batch(csv_content).unwrap();
+
+    // numbers come first in numeric order, and 007 is the same client as 7
+    let expected_result = r#"client, available, held, total, locked
+7, 5.0000, 0.0000, 5.0000, false
+70000, 1.0000, 0.0000, 1.0000, false
+ACC-10, 1.0000, 0.0000, 1.0000, false
+ACC-9, 0.5000, 0.0000, 0.5000, false
+"#;
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+
+    let named: ClientId = "ACC-9".parse().unwrap();
+    assert_eq!(named.name(), Some("ACC-9"));
+    assert_eq!(
+        engine.clients().get_client(named).unwrap().total,
+        Decimal::new(5, 1)
+    );
+    assert_eq!(
+        "70000".parse::<ClientId>().unwrap(),
+        ClientId::from_u64(70000)
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn client_ids_that_overflow_are_errors() {
+    let error = read_transactions("tests/t15_transactions.csv", ClientIdKind::U64).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "client id 'ACC-9' is not a number, it needs string client ids on line 2"
+    );
+
+    let filename = single_row_file("u16", "70000");
+    let error = read_transactions(filename.to_str().unwrap(), ClientIdKind::U16).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "client id 70000 is too large for u16 client ids on line 2"
+    );
+    assert!(read_transactions(filename.to_str().unwrap(), ClientIdKind::U64).is_ok());
+    std::fs::remove_file(&filename).unwrap();
+
+    let filename = single_row_file("u64", "18446744073709551616");
+    let error = read_transactions(filename.to_str().unwrap(), ClientIdKind::U64).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "client id 18446744073709551616 is too large for u64 client ids on line 2"
+    );
+    std::fs::remove_file(&filename).unwrap();
+
+    // leading zeros are still a number, so they fit the default u16 ids
+    let filename = single_row_file("leading-zeros", "007");
+    let csv_content = read_transactions(filename.to_str().unwrap(), ClientIdKind::U16).unwrap();
+    let mut engine = Engine::new(EnginePolicy::default());
+    engine.apply_batch(csv_content).unwrap();
+    assert_eq!(
+        engine
+            .clients()
+            .get_client(ClientId::from(7))
+            .unwrap()
+            .total,
+        Decimal::ONE
+    );
+    std::fs::remove_file(&filename).unwrap();
+
+    // the engine checks transactions that don't come from a file too
+    let mut engine = Engine::new(EnginePolicy::default());
+    let deposit = Transaction::new(
+        TransactionType::Deposit,
+        ClientId::from_u64(70000),
+        TransactionId::from(1),
+        Some(Decimal::ONE),
+    );
+    assert!(matches!(
+        engine.apply(deposit),
+        Err(TransactionError::Rejected { .. })
+    ));
+    assert_eq!(engine.clients().iter().count(), 0);
+}
@@ -0,0 +1,20 @@
+use tps::engine::Engine;
+use tps::read_buffer_to_csv;
+use tps::transactions::processing::ProcessingEvent;
+use tps::transactions::storage::TransactionStore;
+
+// Runs the file through the engine the way the command line does, and
+// returns the event of every row
+pub fn process_file<S: TransactionStore>(
+    engine: &mut Engine<S>,
+    input_csv_filename: &str,
+) -> Vec<ProcessingEvent> {
+    let csv_content = read_buffer_to_csv(input_csv_filename).unwrap();
+    let mut events = Vec::new();
+
+    engine
+        .apply_batch_with_events(csv_content, |event| events.push(event.clone()))
+        .unwrap();
+
+    events
+}
@@ -0,0 +1,224 @@
+mod common;
+
+use std::path::PathBuf;
+
+use common::process_file;
+use rust_decimal::Decimal;
+use tps::clients::{self, ClientId};
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::transactions::processing::{Outcome, TransactionError};
+use tps::transactions::storage::{DiskStore, TransactionStore};
+use tps::transactions::{Transaction, TransactionId, TransactionType};
+
+// Every test gets its own directory so they can run in parallel
+fn store_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tps-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn deposit(tx_id: u32, client_id: u16, amount: Decimal) -> Transaction {
+    Transaction {
+        tx_type: TransactionType::Deposit,
+        client_id: ClientId::from(client_id),
+        tx_id: TransactionId::from(tx_id),
+        amount: Some(amount),
+        in_dispute: false,
+        timestamp: None,
+        disputed_at: None,
+        dispute_action: None,
+        failure: None,
+        settlement: None,
+        captured: None,
+        seq: None,
+        batch: None,
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn disk_store_same_results_as_memory() {
+    for (name, input_csv_filename) in [
+        ("t0", "tests/t0_transactions.csv"),
+        ("t3", "tests/t3_transactions.csv"),
+        ("t17", "tests/t17_transactions.csv"),
+    ] {
+        let mut memory = Engine::new(EnginePolicy::default());
+        process_file(&mut memory, input_csv_filename);
+
+        let dir = store_dir(name);
+        let mut disk = Engine::with_state(
+            clients::ClientPool::new(),
+            DiskStore::open(&dir).unwrap(),
+            EnginePolicy::default(),
+        );
+        process_file(&mut disk, input_csv_filename);
+
+        assert_eq!(
+            memory.clients().format_for_print().unwrap(),
+            disk.clients().format_for_print().unwrap()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn disk_store_keeps_dispute_state_across_reopen() {
+    let dir = store_dir("reopen");
+
+    {
+        let mut store = DiskStore::open(&dir).unwrap();
+        store.insert(deposit(1, 1, Decimal::new(15, 1))).unwrap();
+        store.insert(deposit(2, 2, Decimal::new(21234, 4))).unwrap();
+        assert!(store.set_in_dispute(&TransactionId::from(2), true).unwrap());
+        assert!(!store.set_in_dispute(&TransactionId::from(3), true).unwrap());
+    }
+
+    let store = DiskStore::open(&dir).unwrap();
+    assert!(store.contains(&TransactionId::from(1)).unwrap());
+    assert!(!store.contains(&TransactionId::from(3)).unwrap());
+
+    let disputed = store.get(&TransactionId::from(2)).unwrap().unwrap();
+    assert_eq!(disputed.client_id, ClientId::from(2));
+    assert_eq!(disputed.amount, Some(Decimal::new(21234, 4)));
+    assert!(disputed.in_dispute);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // stores from before the layout had a version can't be read
+    let dir = store_dir("unversioned");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("transactions.dat"), [0u8; 40]).unwrap();
+    assert_eq!(
+        DiskStore::open(&dir).unwrap_err().to_string(),
+        "transaction store has version 1, this build only reads version 3"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn disk_store_sparse_ids() {
+    let dir = store_dir("sparse");
+    let mut store = DiskStore::open(&dir).unwrap();
+
+    store
+        .insert(deposit(10_000_000, 7, Decimal::new(1, 0)))
+        .unwrap();
+
+    assert!(store.contains(&TransactionId::from(10_000_000)).unwrap());
+    assert!(!store.contains(&TransactionId::from(9_999_999)).unwrap());
+    assert!(!store.contains(&TransactionId::from(20_000_000)).unwrap());
+
+    // any numeric client id fits a record, names don't
+    let mut wide = deposit(1, 0, Decimal::new(1, 0));
+    wide.client_id = ClientId::from_u64(u64::MAX);
+    store.insert(wide).unwrap();
+    let stored = store.get(&TransactionId::from(1)).unwrap().unwrap();
+    assert_eq!(stored.client_id, ClientId::from_u64(u64::MAX));
+
+    let mut named = deposit(2, 0, Decimal::new(1, 0));
+    named.client_id = "ACC-9".parse().unwrap();
+    assert_eq!(
+        store.insert(named).unwrap_err().to_string(),
+        "transaction store failure: the disk store only holds numeric client ids, not 'ACC-9'"
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn disk_store_keeps_captured_amounts() {
+    let dir = store_dir("captures");
+    let mut engine = Engine::with_state(
+        clients::ClientPool::new(),
+        DiskStore::open(&dir).unwrap(),
+        EnginePolicy::default(),
+    );
+    let row = |tx_type, tx_id: u32, amount| {
+        Transaction::new(
+            tx_type,
+            ClientId::from(1),
+            TransactionId::from(tx_id),
+            amount,
+        )
+    };
+    engine
+        .apply(row(TransactionType::Deposit, 1, Some(Decimal::new(100, 0))))
+        .unwrap();
+    engine
+        .apply(row(
+            TransactionType::Authorize,
+            2,
+            Some(Decimal::new(40, 0)),
+        ))
+        .unwrap();
+    engine
+        .apply(row(TransactionType::Capture, 2, Some(Decimal::new(25, 0))))
+        .unwrap();
+
+    // the same capture again is a replay, any other amount is a conflict,
+    // and so is capturing the whole authorization
+    assert!(matches!(
+        engine.apply(row(TransactionType::Capture, 2, Some(Decimal::new(25, 0)))),
+        Ok(Outcome::Replay)
+    ));
+    assert!(matches!(
+        engine.apply(row(TransactionType::Capture, 2, Some(Decimal::new(30, 0)))),
+        Err(TransactionError::Conflict(_))
+    ));
+    assert!(matches!(
+        engine.apply(row(TransactionType::Capture, 2, None)),
+        Err(TransactionError::Conflict(_))
+    ));
+
+    let authorization = engine
+        .transactions()
+        .get(&TransactionId::from(2))
+        .unwrap()
+        .unwrap();
+    assert_eq!(authorization.captured, Some(Decimal::new(25, 0)));
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        "client, available, held, total, locked\n1, 75.0000, 0.0000, 75.0000, false\n"
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn disk_store_from_an_earlier_run_is_refused() {
+    let dir = store_dir("rerun");
+    let run = || {
+        std::process::Command::new(env!("CARGO_BIN_EXE_tps"))
+            .args(["tests/t0_transactions.csv", "--store"])
+            .arg(&dir)
+            .output()
+            .unwrap()
+    };
+
+    let first = run();
+    assert!(first.status.success());
+    assert_eq!(
+        String::from_utf8(first.stdout).unwrap(),
+        "client, available, held, total, locked\n\
+         1, 2.5000, 0.0000, 2.5000, false\n\
+         2, 0.0234, 0.0000, 0.0234, true\n\
+         3, 0.0000, 0.0000, 0.0000, false\n\n"
+    );
+
+    // the clients of the first run aren't in the store, so the same input
+    // again would only print the clients its replays leave behind
+    let second = run();
+    assert!(!second.status.success());
+    assert!(second.stdout.is_empty());
+    assert!(String::from_utf8(second.stderr)
+        .unwrap()
+        .contains("already has transactions from an earlier run"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
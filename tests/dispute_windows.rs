@@ -0,0 +1,68 @@
+mod common;
+
+use common::process_file;
+use tps::engine::Engine;
+use tps::policy::{EnginePolicy, SECONDS_PER_DAY};
+
+fn window_policy() -> EnginePolicy {
+    EnginePolicy {
+        dispute_window_secs: Some(120 * SECONDS_PER_DAY),
+        chargeback_window_secs: Some(7 * SECONDS_PER_DAY),
+        ..EnginePolicy::default()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn no_windows_by_default() {
+    // without windows the timestamps are ignored, so the late dispute and
+    // chargeback go through and lock client 1
+    let expected_result = r#"client, available, held, total, locked
+1, 0.0000, 5.0000, 5.0000, true
+2, 0.0000, 0.0000, 0.0000, true
+"#;
+
+    let mut engine = Engine::new(EnginePolicy::default());
+    process_file(&mut engine, "tests/t6_transactions.csv");
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn disputes_and_chargebacks_outside_windows() {
+    // Cases covered here:
+    // - dispute 130 days after the deposit (rejected)
+    // - chargeback 10 days after the dispute opened (rejected, then resolved)
+    // - chargeback 1 day after the dispute opened
+    let expected_result = r#"client, available, held, total, locked
+1, 15.0000, 0.0000, 15.0000, false
+2, 0.0000, 0.0000, 0.0000, true
+"#;
+
+    let mut engine = Engine::new(window_policy());
+    process_file(&mut engine, "tests/t6_transactions.csv");
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn windows_need_timestamps() {
+    // t2 has no timestamp column, so its dispute can't be checked
+    let expected_result = r#"client, available, held, total, locked
+1, 1.5000, 0.0000, 1.5000, false
+2, 2.0000, 0.0000, 2.0000, false
+"#;
+
+    let mut engine = Engine::new(window_policy());
+    process_file(&mut engine, "tests/t2_transactions.csv");
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use common::process_file;
+use tps::clients::ClientPool;
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::transactions::management::{Retention, TransactionTree};
+use tps::transactions::TransactionType;
+
+#[cfg(test)]
+#[test]
+fn replays_are_applied_once() {
+    let expected_result = r#"client, available, held, total, locked
+1, 13.0000, 0.0000, 13.0000, false
+2, 0.0000, 0.0000, 0.0000, true
+"#;
+
+    let mut engine = Engine::new(EnginePolicy::default());
+    process_file(&mut engine, "tests/t8_transactions.csv");
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn replays_and_conflicts_are_told_apart() {
+    let mut engine = Engine::new(EnginePolicy::default());
+    let events = process_file(&mut engine, "tests/t8_transactions.csv");
+
+    let replays: Vec<(TransactionType, u32)> = events
+        .iter()
+        .filter(|event| event.replay)
+        .map(|event| (event.transaction.tx_type, event.transaction.tx_id.into()))
+        .collect();
+    assert_eq!(
+        replays,
+        vec![
+            (TransactionType::Deposit, 1),
+            (TransactionType::Withdrawal, 3),
+            (TransactionType::Dispute, 1),
+            (TransactionType::Resolve, 1),
+            (TransactionType::Chargeback, 4),
+        ]
+    );
+    // replays are acknowledged, not rejected
+    assert!(events
+        .iter()
+        .filter(|event| event.replay)
+        .all(|event| event.is_accepted() && !event.alert));
+
+    let alerts: Vec<(TransactionType, u32)> = events
+        .iter()
+        .filter(|event| event.alert)
+        .map(|event| (event.transaction.tx_type, event.transaction.tx_id.into()))
+        .collect();
+    assert_eq!(
+        alerts,
+        vec![
+            (TransactionType::Deposit, 2),
+            (TransactionType::Withdrawal, 3),
+            (TransactionType::Dispute, 1),
+        ]
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn compact_retention_cant_compare_withdrawals() {
+    let mut engine = Engine::with_state(
+        ClientPool::new(),
+        TransactionTree::with_retention(Retention::Compact),
+        EnginePolicy::default(),
+    );
+    let events = process_file(&mut engine, "tests/t8_transactions.csv");
+
+    // the balances are the same as with full retention, but the repeated
+    // withdrawal is a conflict since only its id was kept
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        "client, available, held, total, locked\n\
+         1, 13.0000, 0.0000, 13.0000, false\n\
+         2, 0.0000, 0.0000, 0.0000, true\n"
+    );
+    let repeated = &events[5];
+    assert!(!repeated.replay && repeated.alert);
+    assert_eq!(
+        repeated.rejection.as_deref(),
+        Some(
+            "Error: Duplicate transaction id, the original is not retained so it can't be compared"
+        )
+    );
+
+    let alerts: Vec<(TransactionType, u32)> = events
+        .iter()
+        .filter(|event| event.alert)
+        .map(|event| (event.transaction.tx_type, event.transaction.tx_id.into()))
+        .collect();
+    assert_eq!(
+        alerts,
+        vec![
+            (TransactionType::Deposit, 2),
+            (TransactionType::Withdrawal, 3),
+            (TransactionType::Withdrawal, 3),
+            (TransactionType::Dispute, 1),
+        ]
+    );
+}
@@ -0,0 +1,126 @@
+use rust_decimal::Decimal;
+use tps::clients::ClientId;
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::risk::{RiskRule, RuleAction, RuleKind};
+use tps::transactions::processing::{Outcome, TransactionError};
+use tps::transactions::{FailureReason, Transaction, TransactionId, TransactionType};
+
+fn transaction(
+    tx_type: TransactionType,
+    client_id: u16,
+    tx_id: u32,
+    amount: Option<i64>,
+) -> Transaction {
+    Transaction::new(
+        tx_type,
+        ClientId::from(client_id),
+        TransactionId::from(tx_id),
+        amount.map(|amount| Decimal::new(amount, 1)),
+    )
+}
+
+#[cfg(test)]
+#[test]
+fn engine_apply_outcomes() {
+    let mut engine = Engine::new(EnginePolicy::default());
+
+    match engine.apply(transaction(TransactionType::Deposit, 1, 1, Some(15))) {
+        Ok(Outcome::Applied(receipt)) => {
+            assert_eq!(receipt.amount, Decimal::new(15, 1));
+            assert_eq!(receipt.total_before, Decimal::ZERO);
+            assert_eq!(receipt.total_after, Decimal::new(15, 1));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(
+        engine
+            .apply(transaction(TransactionType::Deposit, 1, 1, Some(15)))
+            .unwrap(),
+        Outcome::Replay
+    );
+
+    match engine.apply(transaction(TransactionType::Withdrawal, 1, 2, Some(20))) {
+        Err(TransactionError::Rejected { reason, .. }) => {
+            assert_eq!(reason, Some(FailureReason::InsufficientFunds))
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(matches!(
+        engine.apply(transaction(TransactionType::Deposit, 1, 1, Some(16))),
+        Err(TransactionError::Conflict(_))
+    ));
+    assert!(matches!(
+        engine.apply(transaction(TransactionType::Dispute, 1, 9, None)),
+        Err(TransactionError::Rejected { reason: None, .. })
+    ));
+
+    match engine.apply(transaction(TransactionType::Dispute, 1, 1, None)) {
+        Ok(Outcome::Applied(receipt)) => {
+            assert_eq!(receipt.amount, Decimal::new(15, 1));
+            assert_eq!(receipt.available_after, Decimal::ZERO);
+            assert_eq!(receipt.held_after, Decimal::new(15, 1));
+            assert!(!receipt.locked);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let client = engine.clients().get_client(ClientId::from(1)).unwrap();
+    assert_eq!(client.available, Decimal::ZERO);
+    assert_eq!(client.held, Decimal::new(15, 1));
+    assert!(
+        engine
+            .transactions()
+            .get(&TransactionId::from(1))
+            .unwrap()
+            .in_dispute
+    );
+    assert!(!engine.transactions().contains(&TransactionId::from(2)));
+}
+
+#[cfg(test)]
+#[test]
+fn engine_keeps_state_between_batches() {
+    let policy = EnginePolicy {
+        risk_rules: vec![RiskRule {
+            name: String::from("daily-cap"),
+            kind: RuleKind::DailyWithdrawalCap,
+            tx_type: None,
+            limit: Decimal::new(25, 1),
+            last: None,
+            window_secs: None,
+            action: RuleAction::Reject,
+            tier: None,
+        }],
+        ..EnginePolicy::default()
+    };
+    let mut engine = Engine::new(policy);
+    engine
+        .apply_batch(vec![transaction(TransactionType::Deposit, 1, 1, Some(100))])
+        .unwrap();
+
+    // the cap counts what earlier batches withdrew too
+    let mut rejected = Vec::new();
+    for tx_id in 2..=4 {
+        engine
+            .apply_batch_with_events(
+                vec![transaction(TransactionType::Withdrawal, 1, tx_id, Some(10))],
+                |event| {
+                    if !event.is_accepted() {
+                        rejected.push(u32::from(event.transaction.tx_id))
+                    }
+                },
+            )
+            .unwrap();
+    }
+
+    assert_eq!(rejected, vec![4]);
+    assert_eq!(
+        engine
+            .clients()
+            .get_client(ClientId::from(1))
+            .unwrap()
+            .total,
+        Decimal::new(80, 1)
+    );
+}
@@ -0,0 +1,70 @@
+use tps::engine::Engine;
+use tps::generator::{generate, GeneratorConfig};
+use tps::policy::EnginePolicy;
+use tps::{read_buffer_to_csv, reconcile};
+
+#[cfg(test)]
+#[test]
+fn generator_is_reproducible() {
+    let config = GeneratorConfig {
+        seed: 42,
+        rows: 500,
+        duplicate_rate: 0.05,
+        malformed_rate: 0.05,
+        ..GeneratorConfig::default()
+    };
+
+    let first = generate(&config).unwrap();
+    let second = generate(&config).unwrap();
+    assert_eq!(first.rows.len(), 500);
+    assert_eq!(first.rows, second.rows);
+
+    let other = generate(&GeneratorConfig { seed: 43, ..config }).unwrap();
+    assert_ne!(first.rows, other.rows);
+
+    let invalid = GeneratorConfig {
+        dispute_rate: 0.6,
+        malformed_rate: 0.6,
+        ..GeneratorConfig::default()
+    };
+    assert!(generate(&invalid).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn generator_expected_state_matches_engine() {
+    let dir = std::env::temp_dir().join(format!("tps-generator-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input_filename = dir.join("transactions.csv");
+    let expected_filename = dir.join("expected.csv");
+
+    let workload = generate(&GeneratorConfig {
+        seed: 7,
+        rows: 5000,
+        clients: 20,
+        dispute_rate: 0.1,
+        resolve_rate: 0.05,
+        chargeback_rate: 0.02,
+        duplicate_rate: 0.05,
+        malformed_rate: 0.05,
+        ..GeneratorConfig::default()
+    })
+    .unwrap();
+    workload
+        .write_csv(std::fs::File::create(&input_filename).unwrap())
+        .unwrap();
+    workload
+        .write_expected(std::fs::File::create(&expected_filename).unwrap())
+        .unwrap();
+
+    let csv_content = read_buffer_to_csv(input_filename.to_str().unwrap()).unwrap();
+    let mut engine = Engine::new(EnginePolicy::default());
+    engine.apply_batch(csv_content).unwrap();
+
+    let report =
+        reconcile::reconcile_file(expected_filename.to_str().unwrap(), engine.clients()).unwrap();
+    assert!(report.is_clean(), "{}", report);
+    assert!(engine.clients().iter().any(|client| client.locked));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
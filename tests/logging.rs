@@ -0,0 +1,70 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::read_buffer_to_csv;
+
+// Collects everything the subscriber writes
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn rejections_are_structured_events() {
+    let capture = Capture::default();
+    let writer = capture.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .with_max_level(tracing::Level::INFO)
+        .with_writer(move || writer.clone())
+        .finish();
+
+    tracing::subscriber::with_default(subscriber, || {
+        let csv_content = read_buffer_to_csv("tests/t8_transactions.csv").unwrap();
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply_batch(csv_content).unwrap();
+    });
+
+    let output = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+    let events: Vec<serde_json::Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    // accepted transactions are only logged at debug
+    assert!(events
+        .iter()
+        .all(|event| event["fields"]["outcome"] != "accepted"));
+
+    let conflict = events
+        .iter()
+        .find(|event| event["fields"]["outcome"] == "conflict")
+        .unwrap();
+    assert_eq!(conflict["level"], "ERROR");
+    assert_eq!(conflict["fields"]["client_id"], 2);
+    assert_eq!(conflict["fields"]["tx_id"], 2);
+    assert_eq!(conflict["fields"]["tx_type"], "deposit");
+    assert!(conflict["fields"]["reason"]
+        .as_str()
+        .unwrap()
+        .contains("Conflicting duplicate"));
+    assert_eq!(conflict["span"]["name"], "process_transactions");
+
+    let replays = events
+        .iter()
+        .filter(|event| event["fields"]["outcome"] == "replay")
+        .count();
+    assert_eq!(replays, 5);
+}
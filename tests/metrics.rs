@@ -0,0 +1,80 @@
+mod common;
+
+use common::process_file;
+use tps::engine::Engine;
+use tps::metrics::{Metrics, Outcome};
+use tps::policy::EnginePolicy;
+use tps::transactions::TransactionType;
+
+fn metrics_for_file(input_csv_filename: &str) -> Metrics {
+    let mut engine = Engine::new(EnginePolicy::default());
+    let mut metrics = Metrics::new();
+
+    for event in process_file(&mut engine, input_csv_filename) {
+        metrics.record(&event);
+    }
+
+    metrics.observe_clients(engine.clients());
+    metrics
+}
+
+#[cfg(test)]
+#[test]
+fn metrics_count_outcomes() {
+    let metrics = metrics_for_file("tests/t8_transactions.csv");
+
+    assert_eq!(
+        metrics.transactions(TransactionType::Deposit, Outcome::Accepted),
+        3
+    );
+    assert_eq!(
+        metrics.transactions(TransactionType::Deposit, Outcome::Replay),
+        1
+    );
+    assert_eq!(
+        metrics.transactions(TransactionType::Deposit, Outcome::Rejected),
+        1
+    );
+    assert_eq!(
+        metrics.transactions(TransactionType::Dispute, Outcome::Accepted),
+        2
+    );
+    assert_eq!(
+        metrics.transactions(TransactionType::Resolve, Outcome::Rejected),
+        0
+    );
+    assert_eq!(metrics.apply_latency_count(), 16);
+
+    let rendered = metrics.render().unwrap();
+    for line in [
+        "# TYPE tps_transactions_total counter",
+        "tps_transactions_total{type=\"withdrawal\",outcome=\"rejected\"} 1",
+        "tps_transactions_total{type=\"chargeback\",outcome=\"replay\"} 1",
+        "tps_clients 2",
+        "tps_locked_accounts 1",
+        "tps_held_funds 0",
+        "tps_apply_duration_seconds_bucket{le=\"+Inf\"} 16",
+        "tps_apply_duration_seconds_count 16",
+    ] {
+        assert!(rendered.lines().any(|l| l == line), "missing {}", line);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn metrics_textfile() {
+    let dir = std::env::temp_dir().join(format!("tps-metrics-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let filename = dir.join("tps.prom");
+
+    let metrics = metrics_for_file("tests/t0_transactions.csv");
+    metrics.write_textfile(&filename).unwrap();
+
+    assert_eq!(
+        std::fs::read_to_string(&filename).unwrap(),
+        metrics.render().unwrap()
+    );
+    assert!(!dir.join("tps.prom.tmp").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,143 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use rust_decimal::Decimal;
+use tps::clients::ClientId;
+use tps::engine::Engine;
+use tps::observers::{EngineObserver, NdjsonObserver};
+use tps::policy::EnginePolicy;
+use tps::read_buffer_to_csv;
+use tps::transactions::processing::ProcessingEvent;
+use tps::transactions::{Transaction, TransactionId, TransactionType};
+
+// Records which callbacks were called, for which transaction
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+impl Recorder {
+    fn record(&self, name: &str, event: &ProcessingEvent) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", name, u32::from(event.transaction.tx_id)));
+    }
+
+    fn calls(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl EngineObserver for Recorder {
+    fn on_rejected(&mut self, event: &ProcessingEvent) {
+        self.record("rejected", event);
+    }
+
+    fn on_dispute_opened(&mut self, event: &ProcessingEvent) {
+        self.record("dispute_opened", event);
+    }
+
+    fn on_dispute_closed(&mut self, event: &ProcessingEvent) {
+        self.record("dispute_closed", event);
+    }
+
+    fn on_chargeback(&mut self, event: &ProcessingEvent) {
+        self.record("chargeback", event);
+    }
+
+    fn on_account_locked(&mut self, event: &ProcessingEvent) {
+        self.record("account_locked", event);
+    }
+}
+
+// Collects everything the observer writes
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn observers_are_called_for_batches_and_single_transactions() {
+    let recorder = Recorder::default();
+    let mut engine = Engine::new(EnginePolicy::default());
+    engine.add_observer(recorder.clone());
+
+    let csv_content = read_buffer_to_csv("tests/t13_transactions.csv").unwrap();
+    engine.apply_batch(csv_content).unwrap();
+
+    assert_eq!(
+        recorder.calls(),
+        vec![
+            "dispute_opened 2",
+            "dispute_opened 4",
+            "dispute_closed 4",
+            "chargeback 4",
+            "account_locked 4",
+            "rejected 5",
+            "rejected 6",
+        ]
+    );
+
+    // resolving the open dispute one transaction at a time
+    let resolve = Transaction::new(
+        TransactionType::Resolve,
+        ClientId::from(1),
+        TransactionId::from(2),
+        None,
+    );
+    engine.apply(resolve).unwrap();
+    assert_eq!(recorder.calls().last().unwrap(), "dispute_closed 2");
+
+    engine.finish().unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn ndjson_observer_writes_every_callback() {
+    let capture = Capture::default();
+    let mut engine = Engine::new(EnginePolicy::default());
+    engine.add_observer(NdjsonObserver::new(capture.clone()));
+
+    let csv_content = read_buffer_to_csv("tests/t13_transactions.csv").unwrap();
+    engine.apply_batch(csv_content).unwrap();
+    engine.finish().unwrap();
+
+    let output = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<serde_json::Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let events: Vec<&str> = lines
+        .iter()
+        .map(|line| line["event"].as_str().unwrap())
+        .collect();
+
+    assert_eq!(
+        events.iter().filter(|event| **event == "accepted").count(),
+        8
+    );
+    assert_eq!(
+        events.iter().filter(|event| **event == "rejected").count(),
+        2
+    );
+    assert_eq!(
+        events[8..12],
+        ["accepted", "dispute_closed", "chargeback", "account_locked"]
+    );
+    assert_eq!(lines[11]["client"], 2);
+    assert_eq!(lines[11]["amount"], "8.0000");
+    assert!(lines[12]["reason"].as_str().unwrap().contains("locked"));
+
+    let client = engine.clients().get_client(ClientId::from(2)).unwrap();
+    assert!(client.locked);
+    assert_eq!(client.total, Decimal::ZERO);
+}
@@ -0,0 +1,213 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use tps::engine::Engine;
+use tps::outbox::{Outbox, OutboxConsumer, OutboxEvent};
+use tps::policy::EnginePolicy;
+use tps::read_buffer_to_csv;
+
+// Every test gets its own directory so they can run in parallel
+fn outbox_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tps-outbox-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+// The events an uninterrupted run produces
+fn expected_events(input_csv_filename: &str) -> Vec<OutboxEvent> {
+    let csv_content = read_buffer_to_csv(input_csv_filename).unwrap();
+    let mut engine = Engine::new(EnginePolicy::default());
+    let mut events = Vec::new();
+    engine
+        .apply_batch_with_events(csv_content, |event| {
+            if let Some(outbox_event) = OutboxEvent::from_event(events.len() as u64 + 1, event) {
+                events.push(outbox_event);
+            }
+        })
+        .unwrap();
+    events
+}
+
+// A downstream service that keeps the seq of the last event it handled with
+// its own state, so redelivered events are skipped
+#[derive(Default)]
+struct Downstream {
+    handled: Vec<OutboxEvent>,
+    last_seq: u64,
+}
+
+impl Downstream {
+    // Returns the seq of the last event delivered, to acknowledge
+    fn handle(&mut self, consumer: &mut OutboxConsumer, max: usize) -> Option<u64> {
+        let events = consumer.poll(max).unwrap();
+        for event in &events {
+            if event.seq > self.last_seq {
+                self.handled.push(*event);
+                self.last_seq = event.seq;
+            }
+        }
+        events.last().map(|event| event.seq)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn no_lost_or_duplicated_events_across_a_crash() {
+    let dir = outbox_dir("crash");
+    let csv_content = read_buffer_to_csv("tests/t13_transactions.csv").unwrap();
+    let expected = expected_events("tests/t13_transactions.csv");
+    assert_eq!(expected.len(), 8);
+
+    // the producer stops after five rows, in the middle of writing an event
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.set_outbox(Outbox::open(&dir, "t13").unwrap());
+        for transaction in csv_content.iter().take(5) {
+            let _ = engine.apply(*transaction);
+        }
+        assert_eq!(engine.outbox_mut().unwrap().written(), 5);
+
+        let mut log = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join("outbox.log"))
+            .unwrap();
+        log.write_all(br#"{"seq":6,"tx":4,"cli"#).unwrap();
+    }
+
+    // the consumer handles four events but stops before acknowledging the
+    // last two
+    let mut downstream = Downstream::default();
+    {
+        let mut consumer = OutboxConsumer::open(&dir).unwrap();
+        assert_eq!(downstream.handle(&mut consumer, 2), Some(2));
+        consumer.ack(2).unwrap();
+        // the partly written event isn't delivered
+        assert_eq!(downstream.handle(&mut consumer, 10), Some(5));
+        assert_eq!(downstream.last_seq, 5);
+    }
+
+    // the producer runs the input again from the start
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.set_outbox(Outbox::open(&dir, "t13").unwrap());
+        engine.apply_batch(csv_content).unwrap();
+        assert_eq!(engine.outbox_mut().unwrap().written(), 8);
+    }
+
+    // the restarted consumer gets the unacknowledged events again and skips them
+    let mut consumer = OutboxConsumer::open(&dir).unwrap();
+    assert_eq!(consumer.cursor(), 2);
+    while let Some(seq) = downstream.handle(&mut consumer, 2) {
+        consumer.ack(seq).unwrap();
+    }
+    assert_eq!(consumer.cursor(), 8);
+
+    assert_eq!(downstream.handled, expected);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn compaction_drops_acknowledged_events() {
+    let dir = outbox_dir("compact");
+    let expected = expected_events("tests/t13_transactions.csv");
+
+    let mut engine = Engine::new(EnginePolicy::default());
+    engine.set_outbox(Outbox::open(&dir, "t13").unwrap());
+    engine
+        .apply_batch(read_buffer_to_csv("tests/t13_transactions.csv").unwrap())
+        .unwrap();
+
+    let mut consumer = OutboxConsumer::open(&dir).unwrap();
+    assert_eq!(consumer.poll(3).unwrap(), expected[..3]);
+    // events that weren't delivered can't be acknowledged
+    assert!(consumer.ack(4).is_err());
+    consumer.ack(3).unwrap();
+
+    let outbox = engine.outbox_mut().unwrap();
+    assert_eq!(outbox.compact().unwrap(), 3);
+    assert_eq!(outbox.compact().unwrap(), 0);
+    assert_eq!(
+        std::fs::read_to_string(dir.join("outbox.log"))
+            .unwrap()
+            .lines()
+            .count(),
+        5
+    );
+
+    // the rest is still delivered, and a new consumer starts after the cursor
+    assert_eq!(consumer.poll(10).unwrap(), expected[3..]);
+    let mut restarted = OutboxConsumer::open(&dir).unwrap();
+    assert_eq!(restarted.poll(10).unwrap(), expected[3..]);
+
+    // reopening after the compaction doesn't write the dropped events again
+    let mut engine = Engine::new(EnginePolicy::default());
+    engine.set_outbox(Outbox::open(&dir, "t13").unwrap());
+    engine
+        .apply_batch(read_buffer_to_csv("tests/t13_transactions.csv").unwrap())
+        .unwrap();
+    assert_eq!(consumer.poll(10).unwrap(), vec![]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn an_outbox_from_another_input_is_refused() {
+    let dir = outbox_dir("input");
+
+    let mut engine = Engine::new(EnginePolicy::default());
+    engine.set_outbox(Outbox::open(&dir, "t13").unwrap());
+    engine
+        .apply_batch(read_buffer_to_csv("tests/t13_transactions.csv").unwrap())
+        .unwrap();
+    drop(engine);
+
+    // the events of another input would be skipped as already written
+    let e = Outbox::open(&dir, "t14").unwrap_err();
+    assert!(e.to_string().contains("written for input t13, not t14"));
+    assert_eq!(Outbox::open(&dir, "t13").unwrap().written(), 8);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn no_lost_events_across_a_crash_with_a_disk_store() {
+    let dir = outbox_dir("store");
+    let run = |store: &str| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_tps"))
+            .args(["tests/t0_transactions.csv", "--store"])
+            .arg(dir.join(store))
+            .arg("--outbox")
+            .arg(dir.join("outbox"))
+            .output()
+            .unwrap()
+            .status
+            .success()
+    };
+    let log = || std::fs::read_to_string(dir.join("outbox/outbox.log")).unwrap();
+
+    assert!(run("store"));
+    let complete = log();
+    assert_eq!(complete.lines().count(), 10);
+
+    // the run stops after writing five events
+    let first_five: String = complete
+        .lines()
+        .take(5)
+        .map(|line| format!("{}\n", line))
+        .collect();
+    std::fs::write(dir.join("outbox/outbox.log"), &first_five).unwrap();
+
+    // its store already has the rows that would make the other five, they
+    // would only come back as replays
+    assert!(!run("store"));
+    assert_eq!(log(), first_five);
+
+    // from an empty store the missing events are written, and only those
+    assert!(run("store-rerun"));
+    assert_eq!(log(), complete);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use common::process_file;
+use rust_decimal::Decimal;
+use tps::clients::{read_client_profiles, Client, ClientId, ClientPool};
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::transactions::management::TransactionTree;
+
+#[cfg(test)]
+#[test]
+fn overdraft_limit_from_profile() {
+    // client 2 can go down to -100, the third withdrawal would take it to -150
+    let expected_result = r#"client, available, held, total, locked
+1, 10.0000, 0.0000, 10.0000, false
+2, -95.0000, 0.0000, -95.0000, false
+"#;
+
+    let profiles = read_client_profiles("tests/t11_client_profiles.csv").unwrap();
+    let mut engine = Engine::with_state(
+        ClientPool::with_profiles(profiles),
+        TransactionTree::new(),
+        EnginePolicy::default(),
+    );
+    process_file(&mut engine, "tests/t11_transactions.csv");
+
+    let client_pool = engine.clients();
+    assert_eq!(client_pool.format_for_print().unwrap(), expected_result);
+    assert_eq!(
+        client_pool
+            .get_client(ClientId::from(2))
+            .unwrap()
+            .overdraft_limit,
+        Decimal::new(1000, 1)
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn validity_checks_the_overdraft_floor() {
+    let mut client = Client::new_with_values(
+        ClientId::from(1),
+        Decimal::new(-50, 0),
+        Decimal::ZERO,
+        Decimal::new(-50, 0),
+        false,
+    );
+    assert!(!client.check_client_validity());
+
+    client.overdraft_limit = Decimal::new(50, 0);
+    assert!(client.check_client_validity());
+
+    // held can't be negative, whatever the limit
+    client.held = Decimal::new(-1, 0);
+    client.available = Decimal::new(-49, 0);
+    assert!(!client.check_client_validity());
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::process_file;
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::risk::{read_risk_rules, RuleKind};
+
+#[cfg(test)]
+#[test]
+fn empty_config_is_the_default_behaviour() {
+    let policy = EnginePolicy::from_toml_str("").unwrap();
+
+    for input_csv_filename in [
+        "tests/t0_transactions.csv",
+        "tests/t1_transactions.csv",
+        "tests/t2_transactions.csv",
+        "tests/t3_transactions.csv",
+        "tests/t4_transactions.csv",
+        "tests/t12_transactions.csv",
+    ] {
+        let mut configured = Engine::new(policy.clone());
+        process_file(&mut configured, input_csv_filename);
+        let mut default = Engine::new(EnginePolicy::default());
+        process_file(&mut default, input_csv_filename);
+        assert_eq!(
+            configured.clients().format_for_print().unwrap(),
+            default.clients().format_for_print().unwrap()
+        );
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn config_changes_behaviour() {
+    let default_result = r#"client, available, held, total, locked
+1, 7.0000, 0.0000, 7.0000, false
+2, 0.0000, 0.0000, 0.0000, false
+"#;
+    // the withdrawal is charged back and the deposit still goes through on
+    // the locked account, the failed withdrawal doesn't create client 2
+    let configured_result = r#"client, available, held, total, locked
+1, 11.0000, 0.0000, 11.0000, true
+"#;
+
+    let policy = EnginePolicy::from_toml_file("tests/t12_policy.toml").unwrap();
+
+    let mut engine = Engine::new(EnginePolicy::default());
+    process_file(&mut engine, "tests/t12_transactions.csv");
+    assert_eq!(engine.clients().format_for_print().unwrap(), default_result);
+
+    let mut engine = Engine::new(policy);
+    process_file(&mut engine, "tests/t12_transactions.csv");
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        configured_result
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn config_tunables_and_unknown_keys() {
+    let policy = EnginePolicy::from_toml_str(
+        r#"
+dispute_window_secs = 86400
+reorder_window = 10
+reserve_failed_ids = true
+
+[[risk_rules]]
+name = "big-deposit"
+kind = "max_amount"
+type = "deposit"
+limit = "1000"
+action = "reject"
+"#,
+    )
+    .unwrap();
+    assert_eq!(policy.dispute_window_secs, Some(86400));
+    assert_eq!(policy.reorder_window, Some(10));
+    assert_eq!(policy.first_seq, 1);
+    assert!(policy.reserve_failed_ids);
+    assert_eq!(policy.risk_rules[0].kind, RuleKind::MaxAmount);
+
+    assert!(EnginePolicy::from_toml_str("dispute_windw_secs = 10").is_err());
+    assert!(EnginePolicy::from_toml_str("disputable = [\"resolve\"]").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn misspelled_risk_rule_keys() {
+    // without the tier the rule would apply to every client
+    let e = EnginePolicy::from_toml_str(
+        r#"
+[[risk_rules]]
+name = "gold-limit"
+kind = "max_amount"
+limit = "1000"
+action = "reject"
+tire = "gold"
+"#,
+    )
+    .unwrap_err();
+    assert!(format!("{:#}", e).contains("unknown field `tire`"));
+
+    let filename = std::env::temp_dir().join(format!("tps-rules-{}.csv", std::process::id()));
+    std::fs::write(
+        &filename,
+        "name, kind, type, limit, last, window_secs, action, tire\n\
+         gold-limit, max_amount, deposit, 1000, , , reject, gold\n",
+    )
+    .unwrap();
+    assert!(read_risk_rules(filename.to_str().unwrap()).is_err());
+    std::fs::remove_file(&filename).unwrap();
+}
@@ -0,0 +1,100 @@
+use rust_decimal::Decimal;
+use tps::clients::{read_client_profiles, ClientId, ClientPool};
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::read_buffer_to_csv;
+use tps::statements::StatementBuilder;
+use tps::transactions::management::TransactionTree;
+
+fn engine_with_profiles() -> Engine<TransactionTree> {
+    let profiles = read_client_profiles("tests/t16_client_profiles.csv").unwrap();
+    let policy =
+        EnginePolicy::from_toml_str(&std::fs::read_to_string("tests/t16_policy.toml").unwrap())
+            .unwrap();
+    Engine::with_state(
+        ClientPool::with_profiles(profiles),
+        TransactionTree::new(),
+        policy,
+    )
+}
+
+#[cfg(test)]
+#[test]
+fn tier_picks_overdraft_limit_and_risk_rules() {
+    // client 1 gets the gold overdraft of 50, client 3's own limit of 10
+    // wins over its tier's, and only client 2 is in the standard tier the
+    // withdrawal cap applies to
+    let expected_result = r#"client, available, held, total, locked
+1, -40.0000, 0.0000, -40.0000, false
+2, 450.0000, 0.0000, 450.0000, false
+3, 0.0000, 0.0000, 0.0000, false
+4, 5.0000, 0.0000, 5.0000, false
+"#;
+
+    let mut engine = engine_with_profiles();
+    engine
+        .apply_batch(read_buffer_to_csv("tests/t16_transactions.csv").unwrap())
+        .unwrap();
+
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+    let overdraft_limit = |id: u16| {
+        engine
+            .clients()
+            .get_client(ClientId::from(id))
+            .unwrap()
+            .overdraft_limit
+    };
+    assert_eq!(overdraft_limit(1), Decimal::new(500, 1));
+    assert_eq!(overdraft_limit(3), Decimal::new(100, 1));
+    assert_eq!(overdraft_limit(4), Decimal::ZERO);
+}
+
+#[cfg(test)]
+#[test]
+fn profile_details_in_statements_and_json() {
+    let profiles = read_client_profiles("tests/t16_client_profiles.csv").unwrap();
+    let mut builder = StatementBuilder::new(None).with_profiles(profiles);
+    let mut engine = engine_with_profiles();
+    engine
+        .apply_batch_with_events(
+            read_buffer_to_csv("tests/t16_transactions.csv").unwrap(),
+            |event| builder.record(event),
+        )
+        .unwrap();
+
+    let mut output = Vec::new();
+    builder.write_csv(&mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let mut lines = output.lines();
+    assert_eq!(
+        lines.next(),
+        Some("client,tx,type,amount,available,held,pending,total,locked,event,status,reason,overdraft_limit,name,tier,created,risk_rating")
+    );
+    assert_eq!(
+        lines.next(),
+        Some("1,1,deposit,20.0000,20.0000,0.0000,0.0000,20.0000,false,,accepted,,,Ada Lovelace,gold,2023-04-01,low")
+    );
+    // clients without a profile get empty columns
+    assert_eq!(
+        output.lines().last(),
+        Some("4,6,deposit,5.0000,5.0000,0.0000,0.0000,5.0000,false,,accepted,,,,,,")
+    );
+
+    let mut json = Vec::new();
+    builder.write_json(&mut json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json[0]["profile"]["name"], "Ada Lovelace");
+    assert_eq!(json[0]["profile"]["risk_rating"], "low");
+    assert!(json.as_array().unwrap().last().unwrap()["profile"].is_null());
+
+    let mut accounts = Vec::new();
+    engine.clients().write_json(&mut accounts).unwrap();
+    let accounts: serde_json::Value = serde_json::from_slice(&accounts).unwrap();
+    assert_eq!(accounts[2]["client"], 3);
+    assert_eq!(accounts[2]["profile"]["tier"], "gold");
+    assert_eq!(accounts[2]["profile"]["created"], "2022-11-30");
+    assert!(accounts[3].get("profile").is_none());
+}
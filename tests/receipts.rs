@@ -0,0 +1,111 @@
+mod common;
+
+use common::process_file;
+use rust_decimal::Decimal;
+use tps::engine::Engine;
+use tps::outbox::OutboxEvent;
+use tps::policy::EnginePolicy;
+use tps::receipts::{Receipt, ReceiptWriter};
+use tps::transactions::TransactionType;
+
+fn receipts_for_file(input_csv_filename: &str) -> Vec<Receipt> {
+    let mut engine = Engine::new(EnginePolicy::default());
+
+    process_file(&mut engine, input_csv_filename)
+        .iter()
+        .filter_map(Receipt::from_event)
+        .collect()
+}
+
+#[cfg(test)]
+#[test]
+fn receipts_for_applied_transactions() {
+    // the rejected rows on the locked account don't get receipts
+    let receipts = receipts_for_file("tests/t13_transactions.csv");
+    assert_eq!(receipts.len(), 8);
+
+    let chargeback = receipts
+        .iter()
+        .find(|receipt| receipt.tx_type == TransactionType::Chargeback)
+        .unwrap();
+    assert_eq!(chargeback.amount, Decimal::new(8, 0));
+    assert_eq!(chargeback.held_before, Decimal::new(8, 0));
+    assert_eq!(chargeback.total_after, Decimal::ZERO);
+    assert!(chargeback.locked);
+    assert_eq!(receipts.iter().filter(|receipt| receipt.locked).count(), 1);
+
+    // replays don't get receipts either
+    let receipts = receipts_for_file("tests/t8_transactions.csv");
+    let deposits = receipts
+        .iter()
+        .filter(|receipt| receipt.tx_type == TransactionType::Deposit)
+        .count();
+    assert_eq!(deposits, 3);
+}
+
+#[cfg(test)]
+#[test]
+fn receipts_stream_to_csv_and_json() {
+    let receipts = receipts_for_file("tests/t13_transactions.csv");
+
+    let mut csv_output = Vec::new();
+    let mut writer = ReceiptWriter::csv(&mut csv_output);
+    for receipt in &receipts {
+        writer.write(receipt).unwrap();
+    }
+    writer.flush().unwrap();
+    drop(writer);
+
+    let csv_output = String::from_utf8(csv_output).unwrap();
+    let mut lines = csv_output.lines();
+    assert_eq!(
+        lines.next().unwrap(),
+        "tx,client,type,amount,available_before,held_before,pending_before,total_before,available_after,held_after,pending_after,total_after,locked"
+    );
+    assert_eq!(
+        lines.next().unwrap(),
+        "1,1,deposit,10.0000,0.0000,0.0000,0.0000,0.0000,10.0000,0.0000,0.0000,10.0000,false"
+    );
+    assert_eq!(lines.count(), receipts.len() - 1);
+
+    let mut json_output = Vec::new();
+    let mut writer = ReceiptWriter::json(&mut json_output);
+    for receipt in &receipts {
+        writer.write(receipt).unwrap();
+    }
+    writer.flush().unwrap();
+    drop(writer);
+
+    let json_output = String::from_utf8(json_output).unwrap();
+    let lines: Vec<serde_json::Value> = json_output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), receipts.len());
+    assert_eq!(lines[6]["type"], "chargeback");
+    assert_eq!(lines[6]["locked"], true);
+    assert_eq!(lines[6]["held_before"], "8.0000");
+}
+
+#[cfg(test)]
+#[test]
+fn receipts_and_outbox_events_show_pending_funds() {
+    let receipts = receipts_for_file("tests/t17_transactions.csv");
+
+    // the authorization moves 40.0 from available to pending
+    let authorization = receipts[1];
+    assert_eq!(authorization.tx_type, TransactionType::Authorize);
+    assert_eq!(authorization.pending_before, Decimal::ZERO);
+    assert_eq!(authorization.pending_after, Decimal::new(400000, 4));
+    assert_eq!(authorization.total_after, Decimal::new(1000000, 4));
+
+    // the partial capture takes the whole authorization out of pending
+    let mut engine = Engine::new(EnginePolicy::default());
+    let mut events = Vec::new();
+    for event in process_file(&mut engine, "tests/t17_transactions.csv") {
+        events.extend(OutboxEvent::from_event(events.len() as u64 + 1, &event));
+    }
+    assert_eq!(events[2].tx_type, TransactionType::Capture);
+    assert_eq!(events[2].pending, Decimal::ZERO);
+    assert_eq!(events[2].total, Decimal::new(750000, 4));
+}
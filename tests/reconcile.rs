@@ -0,0 +1,119 @@
+mod common;
+
+use common::process_file;
+use rust_decimal::Decimal;
+use tps::clients;
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::reconcile::{self, BalanceField, Discrepancy};
+
+#[cfg(test)]
+#[test]
+fn reconcile_matching_state() {
+    let mut engine = Engine::new(EnginePolicy::default());
+    process_file(&mut engine, "tests/t3_transactions.csv");
+    let client_pool = engine.clients();
+
+    let report = reconcile::reconcile_file("tests/t3_expected.csv", client_pool).unwrap();
+
+    assert!(report.is_clean());
+    assert_eq!(report.matched, 2);
+}
+
+#[cfg(test)]
+#[test]
+fn reconcile_reports_discrepancies() {
+    let mut engine = Engine::new(EnginePolicy::default());
+    process_file(&mut engine, "tests/t3_transactions.csv");
+    let client_pool = engine.clients();
+
+    let report = reconcile::reconcile_file("tests/t3_expected_mismatch.csv", client_pool).unwrap();
+
+    assert!(!report.is_clean());
+    assert_eq!(report.matched, 0);
+    assert_eq!(
+        report.missing_clients().collect::<Vec<_>>(),
+        vec![clients::ClientId::from(3)]
+    );
+    assert_eq!(
+        report.extra_clients().collect::<Vec<_>>(),
+        vec![clients::ClientId::from(2)]
+    );
+
+    // client 1 is off by 0.2 in available and total, and should be locked
+    assert_eq!(
+        report.mismatches().cloned().collect::<Vec<_>>(),
+        vec![
+            Discrepancy::Balance {
+                client_id: clients::ClientId::from(1),
+                field: BalanceField::Available,
+                expected: Decimal::new(5, 1),
+                actual: Decimal::new(3, 1),
+                delta: Decimal::new(-2, 1),
+            },
+            Discrepancy::Balance {
+                client_id: clients::ClientId::from(1),
+                field: BalanceField::Total,
+                expected: Decimal::new(5, 1),
+                actual: Decimal::new(3, 1),
+                delta: Decimal::new(-2, 1),
+            },
+            Discrepancy::Locked {
+                client_id: clients::ClientId::from(1),
+                expected: false,
+                actual: true,
+            },
+        ]
+    );
+}
+
+#[cfg(test)]
+#[test]
+#[should_panic]
+fn reconcile_duplicate_expected_client() {
+    let mut engine = Engine::new(EnginePolicy::default());
+    process_file(&mut engine, "tests/t1_transactions.csv");
+    let client_pool = engine.clients();
+
+    reconcile::reconcile_file("tests/t1_expected_duplicate.csv", client_pool).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn reconcile_pending_funds() {
+    let mut engine = Engine::new(EnginePolicy::default());
+    process_file(&mut engine, "tests/t17_transactions.csv");
+    let client_pool = engine.clients();
+
+    // the output has a pending column, client 2 still has 5.0 authorized
+    let output = client_pool.format_for_print().unwrap();
+    let filename =
+        std::env::temp_dir().join(format!("tps-reconcile-pending-{}.csv", std::process::id()));
+    std::fs::write(&filename, &output).unwrap();
+    let report = reconcile::reconcile_file(filename.to_str().unwrap(), client_pool).unwrap();
+    assert!(report.is_clean());
+
+    // an expectation without the pending column expects none
+    let without_pending: String = output
+        .lines()
+        .map(|line| {
+            let mut columns: Vec<&str> = line.split(", ").collect();
+            columns.remove(3);
+            columns.join(", ") + "\n"
+        })
+        .collect();
+    std::fs::write(&filename, without_pending).unwrap();
+    let report = reconcile::reconcile_file(filename.to_str().unwrap(), client_pool).unwrap();
+    assert_eq!(
+        report.mismatches().cloned().collect::<Vec<_>>(),
+        vec![Discrepancy::Balance {
+            client_id: clients::ClientId::from(2),
+            field: BalanceField::Pending,
+            expected: Decimal::ZERO,
+            actual: Decimal::new(50000, 4),
+            delta: Decimal::new(50000, 4),
+        }]
+    );
+
+    std::fs::remove_file(&filename).unwrap();
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use common::process_file;
+use tps::clients::ClientId;
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::transactions::reorder::{ReorderBuffer, Reordered};
+use tps::transactions::{Transaction, TransactionId, TransactionType};
+
+#[cfg(test)]
+#[test]
+fn input_order_without_reorder_window() {
+    // the dispute arrives before its deposit and is rejected
+    let expected_result = r#"client, available, held, total, locked
+1, 15.0000, 0.0000, 15.0000, false
+2, 4.0000, 0.0000, 4.0000, false
+3, 1.0000, 0.0000, 1.0000, false
+"#;
+
+    let mut engine = Engine::new(EnginePolicy::default());
+    process_file(&mut engine, "tests/t7_transactions.csv");
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn reorder_by_sequence_number() {
+    // Cases covered here:
+    // - dispute held until its deposit arrives
+    // - row held past the window because sequence number 5 never arrives
+    // - repeated sequence number
+    // - row still held at the end of the input
+    let expected_result = r#"client, available, held, total, locked
+1, 4.0000, 2.0000, 6.0000, false
+2, 3.0000, 0.0000, 3.0000, false
+"#;
+
+    let policy = EnginePolicy {
+        reorder_window: Some(2),
+        ..EnginePolicy::default()
+    };
+    let mut engine = Engine::new(policy);
+    let events = process_file(&mut engine, "tests/t7_transactions.csv");
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+
+    let processed_seqs: Vec<u64> = events
+        .iter()
+        .filter(|event| event.is_accepted())
+        .filter_map(|event| event.transaction.seq)
+        .collect();
+    assert_eq!(processed_seqs, vec![1, 2, 3, 4, 7, 8, 9]);
+
+    let rejected_seqs: Vec<u64> = events
+        .iter()
+        .filter(|event| !event.is_accepted())
+        .filter_map(|event| event.transaction.seq)
+        .collect();
+    assert_eq!(rejected_seqs, vec![6, 2, 20]);
+}
+
+#[cfg(test)]
+#[test]
+fn every_held_row_ages() {
+    let row = |seq: u64| {
+        let mut transaction = Transaction::new(
+            TransactionType::Deposit,
+            ClientId::from(1),
+            TransactionId::from(seq as u32),
+            None,
+        );
+        transaction.seq = Some(seq);
+        transaction
+    };
+    let decided = |rows: Vec<Reordered>| -> Vec<(&'static str, Option<u64>)> {
+        rows.iter()
+            .map(|row| {
+                let outcome = match row {
+                    Reordered::Ready(_) => "ready",
+                    Reordered::Expired(_) => "expired",
+                    Reordered::Late(_) => "late",
+                };
+                (outcome, row.transaction().seq)
+            })
+            .collect()
+    };
+
+    // 3 has waited a row when 2 arrives, so it is given up on with 2 under it
+    let mut buffer = ReorderBuffer::new(1, 0);
+    assert_eq!(decided(buffer.push(row(3))), vec![]);
+    assert_eq!(
+        decided(buffer.push(row(2))),
+        vec![("expired", Some(2)), ("expired", Some(3))]
+    );
+    assert_eq!(decided(buffer.push(row(4))), vec![("ready", Some(4))]);
+
+    // nothing comes after the last sequence number
+    let mut buffer = ReorderBuffer::new(u64::MAX - 2, 0);
+    assert_eq!(decided(buffer.push(row(u64::MAX))), vec![]);
+    assert_eq!(
+        decided(buffer.push(row(u64::MAX - 1))),
+        vec![("expired", Some(u64::MAX - 1)), ("expired", Some(u64::MAX))]
+    );
+    assert_eq!(
+        decided(buffer.push(row(u64::MAX))),
+        vec![("late", Some(u64::MAX))]
+    );
+
+    let mut buffer = ReorderBuffer::new(u64::MAX, 1);
+    assert_eq!(
+        decided(buffer.push(row(u64::MAX))),
+        vec![("ready", Some(u64::MAX))]
+    );
+    assert_eq!(
+        decided(buffer.push(row(u64::MAX))),
+        vec![("late", Some(u64::MAX))]
+    );
+    assert!(buffer.is_empty());
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use common::process_file;
+use tps::clients::{ClientId, ClientPool};
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::transactions::storage::{DiskStore, TransactionStore};
+use tps::transactions::{FailureReason, TransactionId};
+
+fn reserving() -> EnginePolicy {
+    EnginePolicy {
+        reserve_failed_ids: true,
+        ..EnginePolicy::default()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn failed_ids_can_be_reused_by_default() {
+    // the deposit reuses the id of the failed withdrawal and is then disputed
+    let expected_result = r#"client, available, held, total, locked
+1, 10.0000, 5.0000, 15.0000, false
+2, 0.0000, 0.0000, 0.0000, false
+"#;
+
+    let mut engine = Engine::new(EnginePolicy::default());
+    process_file(&mut engine, "tests/t9_transactions.csv");
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn failed_ids_are_reserved() {
+    let expected_result = r#"client, available, held, total, locked
+1, 10.0000, 0.0000, 10.0000, false
+2, 0.0000, 0.0000, 0.0000, false
+"#;
+
+    let mut engine = Engine::new(reserving());
+    let events = process_file(&mut engine, "tests/t9_transactions.csv");
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+
+    let tree = engine.transactions();
+    let failed = tree.get(&TransactionId::from(2)).unwrap();
+    assert_eq!(failed.failure, Some(FailureReason::InsufficientFunds));
+    let failed = tree.get(&TransactionId::from(3)).unwrap();
+    assert_eq!(failed.client_id, ClientId::from(2));
+    assert_eq!(failed.failure, Some(FailureReason::MissingAmount));
+
+    // the reused id is a conflict, the disputes name the original failure
+    assert!(events[2].alert);
+    assert_eq!(
+        events[3].rejection.as_deref(),
+        Some("Error: Provided transaction was rejected (insufficient funds) and can't be disputed")
+    );
+    assert_eq!(
+        events[5].rejection.as_deref(),
+        Some("Error: Provided transaction was rejected (missing amount) and can't be disputed")
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn failed_ids_are_reserved_on_disk() {
+    let dir = std::env::temp_dir().join(format!("tps-reserved-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    {
+        let store = DiskStore::open(&dir).unwrap();
+        let mut engine = Engine::with_state(ClientPool::new(), store, reserving());
+        process_file(&mut engine, "tests/t9_transactions.csv");
+    }
+
+    let store = DiskStore::open(&dir).unwrap();
+    let failed = store.get(&TransactionId::from(2)).unwrap().unwrap();
+    assert_eq!(failed.failure, Some(FailureReason::InsufficientFunds));
+    assert_eq!(
+        store.get(&TransactionId::from(1)).unwrap().unwrap().failure,
+        None
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,73 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicIsize, Ordering};
+
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::transactions::management::{Retention, TransactionTree};
+use tps::{clients, read_buffer_to_csv};
+
+// Tracks the number of live heap bytes so the transaction tree can be
+// measured. This file only has one test so nothing else allocates at the
+// same time.
+struct CountingAllocator;
+
+static LIVE_BYTES: AtomicIsize = AtomicIsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE_BYTES.fetch_add(layout.size() as isize, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_BYTES.fetch_sub(layout.size() as isize, Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn tree_bytes(retention: Retention) -> isize {
+    let csv_content = read_buffer_to_csv("tests/t5_transactions.csv").unwrap();
+    let mut engine = Engine::with_state(
+        clients::ClientPool::new(),
+        TransactionTree::with_retention(retention),
+        EnginePolicy::default(),
+    );
+
+    engine.apply_batch(csv_content).unwrap();
+    let (_, transations) = engine.into_parts();
+    // the input vector is consumed and freed during processing, so the
+    // tree is measured by how much dropping it gives back
+    let before = LIVE_BYTES.load(Ordering::SeqCst);
+    drop(transations);
+    before - LIVE_BYTES.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+#[test]
+fn compact_retention_uses_less_memory() {
+    let full = tree_bytes(Retention::Full);
+    let compact = tree_bytes(Retention::Compact);
+    assert!(
+        compact * 10 < full,
+        "full {} bytes, compact {}",
+        full,
+        compact
+    );
+
+    // a compact deposit is 16 bytes plus its share of the map it is in, the
+    // withdrawals only take a bit each
+    let deposits = std::fs::read_to_string("tests/t5_transactions.csv")
+        .unwrap()
+        .lines()
+        .filter(|line| line.starts_with("deposit"))
+        .count() as isize;
+    assert!(
+        compact < deposits * 48,
+        "compact retention took {} bytes for {} deposits",
+        compact,
+        deposits
+    );
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use common::process_file;
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::risk::{read_risk_rules, RuleAction};
+
+// the rules a transaction hit, by name
+type RuleHits<'a> = Vec<(&'a str, RuleAction)>;
+
+#[cfg(test)]
+#[test]
+fn risk_rules_reject_and_flag() {
+    let expected_result = r#"client, available, held, total, locked
+1, 480.0000, 0.0000, 480.0000, false
+2, 391.0000, 0.0000, 391.0000, false
+"#;
+
+    let policy = EnginePolicy {
+        risk_rules: read_risk_rules("tests/t10_risk_rules.csv").unwrap(),
+        ..EnginePolicy::default()
+    };
+    let mut engine = Engine::new(policy);
+    let events = process_file(&mut engine, "tests/t10_transactions.csv");
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+
+    let hits: Vec<(u32, bool, RuleHits)> = events
+        .iter()
+        .filter(|event| !event.rule_hits.is_empty())
+        .map(|event| {
+            (
+                event.transaction.tx_id.into(),
+                event.is_accepted(),
+                event
+                    .rule_hits
+                    .iter()
+                    .map(|hit| (hit.rule.as_str(), hit.action))
+                    .collect(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        hits,
+        vec![
+            (2, false, vec![("big-deposit", RuleAction::Reject)]),
+            (
+                5,
+                false,
+                vec![
+                    ("withdrawal-burst", RuleAction::Reject),
+                    ("hourly", RuleAction::Flag),
+                ]
+            ),
+            (
+                10,
+                false,
+                vec![
+                    ("hourly", RuleAction::Flag),
+                    ("daily-cap", RuleAction::Reject)
+                ]
+            ),
+        ]
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn risk_rules_need_a_velocity_bound() {
+    let dir = std::env::temp_dir().join(format!("tps-risk-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let filename = dir.join("rules.csv");
+    std::fs::write(
+        &filename,
+        "name, kind, type, limit, last, window_secs, action\nburst, velocity, , 3, , , flag\n",
+    )
+    .unwrap();
+
+    assert!(read_risk_rules(filename.to_str().unwrap()).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn risk_windows_near_the_end_of_time() {
+    let dir = std::env::temp_dir().join(format!("tps-risk-late-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let filename = dir.join("transactions.csv");
+    let mut rows = String::from("type, client, tx, amount, timestamp\n");
+    for tx in 1..=4u64 {
+        rows += &format!("deposit, 1, {}, 1.0, {}\n", tx, 18446744073709551000 + tx);
+    }
+    std::fs::write(&filename, rows).unwrap();
+
+    let policy = EnginePolicy {
+        risk_rules: read_risk_rules("tests/t10_risk_rules.csv").unwrap(),
+        ..EnginePolicy::default()
+    };
+    let mut engine = Engine::new(policy);
+    let events = process_file(&mut engine, filename.to_str().unwrap());
+
+    // the hourly window reaches past u64::MAX, the fourth deposit is still
+    // counted in it
+    let flagged: Vec<bool> = events
+        .iter()
+        .map(|event| !event.rule_hits.is_empty())
+        .collect();
+    assert_eq!(flagged, vec![false, false, false, true]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use common::process_file;
+use tps::clients::ClientId;
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::statements::{StatementBuilder, StatementStatus};
+
+fn build_statements(input_csv_filename: &str, client_id: Option<ClientId>) -> StatementBuilder {
+    let mut engine = Engine::new(EnginePolicy::default());
+    let mut builder = StatementBuilder::new(client_id);
+
+    for event in process_file(&mut engine, input_csv_filename) {
+        builder.record(&event);
+    }
+
+    builder
+}
+
+#[cfg(test)]
+#[test]
+fn single_client_statement_csv() {
+    let builder = build_statements("tests/t0_transactions.csv", Some(ClientId::from(2)));
+
+    let mut output = Vec::new();
+    builder.write_csv(&mut output).unwrap();
+
+    let expected_result = r#"client,tx,type,amount,available,held,pending,total,locked,event,status,reason
+2,2,deposit,2.1234,2.1234,0.0000,0.0000,2.1234,false,,accepted,
+2,5,withdrawal,3.0000,2.1234,0.0000,0.0000,2.1234,false,,rejected,Error: Client does not have enough available balance to withdraw
+2,2,dispute,2.1234,0.0000,2.1234,0.0000,2.1234,false,,accepted,
+2,7,deposit,1.1234,1.1234,2.1234,0.0000,3.2468,false,,accepted,
+2,8,withdrawal,1.1000,0.0234,2.1234,0.0000,2.1468,false,,accepted,
+2,2,chargeback,2.1234,0.0234,0.0000,0.0000,0.0234,true,locked,accepted,
+2,9,deposit,1.0000,0.0234,0.0000,0.0000,0.0234,true,,rejected,"client ClientId(2) is locked, cannot process deposit"
+"#;
+
+    assert_eq!(String::from_utf8(output).unwrap(), expected_result);
+}
+
+#[cfg(test)]
+#[test]
+fn all_client_statements() {
+    let builder = build_statements("tests/t3_transactions.csv", None);
+
+    // every input row shows up exactly once
+    assert_eq!(builder.lines().count(), 17);
+    assert_eq!(builder.statement(ClientId::from(1)).len(), 14);
+    assert_eq!(builder.statement(ClientId::from(2)).len(), 3);
+
+    let rejected = builder
+        .lines()
+        .filter(|line| line.status == StatementStatus::Rejected)
+        .count();
+    assert!(rejected > 0);
+
+    let mut output = Vec::new();
+    builder.write_json(&mut output).unwrap();
+    let json: Vec<serde_json::Value> = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json.len(), 17);
+}
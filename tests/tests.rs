@@ -1,4 +1,6 @@
-use tps::{clients, read_buffer_to_csv, transactions};
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::read_buffer_to_csv;
 
 #[cfg(test)]
 #[test]
@@ -16,12 +18,10 @@ fn basic_deposit_ignore() {
 
     let csv_content = read_buffer_to_csv(input_csv_filename).unwrap();
 
-    let mut client_pool = clients::ClientPool::new();
-    let mut transations = transactions::management::TransactionTree::new();
+    let mut engine = Engine::new(EnginePolicy::default());
 
     //process the transactions
-    transactions::processing::process_transactions(csv_content, &mut client_pool, &mut transations)
-        .unwrap();
+    engine.apply_batch(csv_content).unwrap();
 
     // check here the values in the client pool
     let expected_result = r#"client, available, held, total, locked
@@ -29,7 +29,10 @@ fn basic_deposit_ignore() {
 2, 0.0000, 0.0000, 0.0000, false
 "#;
 
-    assert_eq!(client_pool.format_for_print().unwrap(), expected_result);
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
 }
 
 #[cfg(test)]
@@ -39,12 +42,10 @@ fn my_test_data() {
 
     let csv_content = read_buffer_to_csv(input_csv_filename).unwrap();
 
-    let mut client_pool = clients::ClientPool::new();
-    let mut transations = transactions::management::TransactionTree::new();
+    let mut engine = Engine::new(EnginePolicy::default());
 
     //process the transactions
-    transactions::processing::process_transactions(csv_content, &mut client_pool, &mut transations)
-        .unwrap();
+    engine.apply_batch(csv_content).unwrap();
 
     // check here the values in the client pool
     let expected_result = r#"client, available, held, total, locked
@@ -53,7 +54,10 @@ fn my_test_data() {
 3, 0.0000, 0.0000, 0.0000, false
 "#;
 
-    assert_eq!(client_pool.format_for_print().unwrap(), expected_result);
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
 }
 
 #[cfg(test)]
@@ -63,12 +67,10 @@ fn basic_dispute() {
 
     let csv_content = read_buffer_to_csv(input_csv_filename).unwrap();
 
-    let mut client_pool = clients::ClientPool::new();
-    let mut transations = transactions::management::TransactionTree::new();
+    let mut engine = Engine::new(EnginePolicy::default());
 
     //process the transactions
-    transactions::processing::process_transactions(csv_content, &mut client_pool, &mut transations)
-        .unwrap();
+    engine.apply_batch(csv_content).unwrap();
 
     // check here the values in the client pool
     let expected_result = r#"client, available, held, total, locked
@@ -76,7 +78,10 @@ fn basic_dispute() {
 2, 2.0000, 0.0000, 2.0000, false
 "#;
 
-    assert_eq!(client_pool.format_for_print().unwrap(), expected_result);
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
 }
 
 #[cfg(test)]
@@ -86,12 +91,10 @@ fn advanced_dispute() {
 
     let csv_content = read_buffer_to_csv(input_csv_filename).unwrap();
 
-    let mut client_pool = clients::ClientPool::new();
-    let mut transations = transactions::management::TransactionTree::new();
+    let mut engine = Engine::new(EnginePolicy::default());
 
     //process the transactions
-    transactions::processing::process_transactions(csv_content, &mut client_pool, &mut transations)
-        .unwrap();
+    engine.apply_batch(csv_content).unwrap();
 
     // Cases covered here:
     // - duplicate transaction ids
@@ -106,7 +109,10 @@ fn advanced_dispute() {
 2, 1.1250, 0.0000, 1.1250, false
 "#;
 
-    assert_eq!(client_pool.format_for_print().unwrap(), expected_result);
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
 }
 
 #[cfg(test)]
@@ -116,19 +122,20 @@ fn many_transactions() {
 
     let csv_content = read_buffer_to_csv(input_csv_filename).unwrap();
 
-    let mut client_pool = clients::ClientPool::new();
-    let mut transations = transactions::management::TransactionTree::new();
+    let mut engine = Engine::new(EnginePolicy::default());
 
     //process the transactions
-    transactions::processing::process_transactions(csv_content, &mut client_pool, &mut transations)
-        .unwrap();
+    engine.apply_batch(csv_content).unwrap();
 
     // check here the values in the client pool
     let expected_result = r#"client, available, held, total, locked
 1, 1.0000, 0.0000, 1.0000, false
 "#;
 
-    assert_eq!(client_pool.format_for_print().unwrap(), expected_result);
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
 }
 
 #[cfg(test)]
@@ -138,12 +145,10 @@ fn many_transactions_many_accounts() {
 
     let csv_content = read_buffer_to_csv(input_csv_filename).unwrap();
 
-    let mut client_pool = clients::ClientPool::new();
-    let mut transations = transactions::management::TransactionTree::new();
+    let mut engine = Engine::new(EnginePolicy::default());
 
     //process the transactions
-    transactions::processing::process_transactions(csv_content, &mut client_pool, &mut transations)
-        .unwrap();
+    engine.apply_batch(csv_content).unwrap();
 
     // check here the values in the client pool
     let expected_result = r#"client, available, held, total, locked
@@ -248,5 +253,37 @@ fn many_transactions_many_accounts() {
 99, 1.0000, 0.0000, 1.0000, false
 "#;
 
-    assert_eq!(client_pool.format_for_print().unwrap(), expected_result);
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn dispute_of_another_clients_transaction() {
+    let csv_content = read_buffer_to_csv("tests/t21_transactions.csv").unwrap();
+
+    let mut engine = Engine::new(EnginePolicy::default());
+    let mut rejections = Vec::new();
+    engine
+        .apply_batch_with_events(csv_content, |event| {
+            rejections.extend(event.rejection.clone());
+        })
+        .unwrap();
+
+    // client 2 can't hold, release or charge back client 1's deposit
+    assert_eq!(
+        rejections,
+        vec!["Error: Provided transaction belongs to a different client"; 4]
+    );
+    let expected_result = r#"client, available, held, total, locked
+1, 10.0000, 0.0000, 10.0000, false
+2, 5.0000, 0.0000, 5.0000, false
+"#;
+    assert_eq!(
+        engine.clients().format_for_print().unwrap(),
+        expected_result
+    );
+    assert_eq!(engine.transactions().open_disputes().count(), 0);
 }
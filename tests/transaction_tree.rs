@@ -0,0 +1,149 @@
+mod common;
+
+use common::process_file;
+use rust_decimal::Decimal;
+use tps::clients::ClientId;
+use tps::engine::Engine;
+use tps::policy::EnginePolicy;
+use tps::transactions::management::{Retention, TransactionTree};
+use tps::transactions::TransactionId;
+use tps::{clients, transactions};
+
+fn tx_ids(transactions: impl Iterator<Item = transactions::Transaction>) -> Vec<TransactionId> {
+    transactions.map(|tx| tx.tx_id).collect()
+}
+
+fn ids(tx_ids: &[u32]) -> Vec<TransactionId> {
+    tx_ids.iter().copied().map(TransactionId::from).collect()
+}
+
+fn tree_for_file(input_csv_filename: &str, retention: Retention) -> TransactionTree {
+    let mut engine = Engine::with_state(
+        clients::ClientPool::new(),
+        TransactionTree::with_retention(retention),
+        EnginePolicy::default(),
+    );
+
+    process_file(&mut engine, input_csv_filename);
+    let (_, transations) = engine.into_parts();
+
+    transations
+}
+
+#[cfg(test)]
+#[test]
+fn transactions_for_client() {
+    let tree = tree_for_file("tests/t0_transactions.csv", Retention::Full);
+
+    assert_eq!(
+        tx_ids(tree.transactions_for_client(ClientId::from(1))),
+        ids(&[1, 3, 4])
+    );
+    assert_eq!(
+        tx_ids(tree.transactions_for_client(ClientId::from(2))),
+        ids(&[2, 7, 8])
+    );
+    assert_eq!(
+        tx_ids(tree.transactions_for_client(ClientId::from(3))),
+        ids(&[])
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn open_disputes_follow_dispute_state() {
+    // t2 leaves the dispute on transaction 1 open
+    let mut tree = tree_for_file("tests/t2_transactions.csv", Retention::Full);
+
+    assert_eq!(tx_ids(tree.open_disputes()), ids(&[1]));
+    assert_eq!(
+        tx_ids(tree.open_disputes_for_client(ClientId::from(1))),
+        ids(&[1])
+    );
+    assert_eq!(
+        tx_ids(tree.open_disputes_for_client(ClientId::from(2))),
+        ids(&[])
+    );
+
+    assert!(tree.set_in_dispute(&TransactionId::from(2), true));
+    assert_eq!(tx_ids(tree.open_disputes()), ids(&[1, 2]));
+    assert_eq!(
+        tx_ids(tree.open_disputes_for_client(ClientId::from(2))),
+        ids(&[2])
+    );
+
+    assert!(tree.set_in_dispute(&TransactionId::from(1), false));
+    assert_eq!(tx_ids(tree.open_disputes()), ids(&[2]));
+
+    assert!(!tree.set_in_dispute(&TransactionId::from(99), true));
+}
+
+#[cfg(test)]
+#[test]
+fn resolved_and_charged_back_disputes_are_closed() {
+    let tree = tree_for_file("tests/t0_transactions.csv", Retention::Full);
+
+    assert_eq!(tree.open_disputes().count(), 0);
+}
+
+#[cfg(test)]
+#[test]
+fn transactions_in_id_range() {
+    let tree = tree_for_file("tests/t0_transactions.csv", Retention::Full);
+
+    assert_eq!(
+        tx_ids(tree.range(TransactionId::from(2)..=TransactionId::from(7))),
+        ids(&[2, 3, 4, 7])
+    );
+    assert_eq!(tx_ids(tree.range(TransactionId::from(8)..)), ids(&[8]));
+}
+
+#[cfg(test)]
+#[test]
+fn compact_retention_keeps_deposits() {
+    let tree = tree_for_file("tests/t0_transactions.csv", Retention::Compact);
+
+    // withdrawals are only kept for duplicate detection
+    assert_eq!(tree.len(), 6);
+    assert!(tree.contains(&TransactionId::from(4)));
+    assert!(tree.get(&TransactionId::from(4)).is_none());
+
+    let deposit = tree.get(&TransactionId::from(2)).unwrap();
+    assert_eq!(deposit.client_id, ClientId::from(2));
+    assert_eq!(deposit.amount, Some(Decimal::new(21234, 4)));
+
+    assert_eq!(tx_ids(tree.range(..)), ids(&[1, 2, 3, 7]));
+    assert_eq!(
+        tx_ids(tree.transactions_for_client(ClientId::from(2))),
+        ids(&[2, 7])
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn compact_retention_same_results() {
+    for input_csv_filename in [
+        "tests/t0_transactions.csv",
+        "tests/t2_transactions.csv",
+        "tests/t3_transactions.csv",
+    ] {
+        let [full, compact] = [Retention::Full, Retention::Compact].map(|retention| {
+            let mut engine = Engine::with_state(
+                clients::ClientPool::new(),
+                TransactionTree::with_retention(retention),
+                EnginePolicy::default(),
+            );
+            process_file(&mut engine, input_csv_filename);
+            engine
+        });
+
+        assert_eq!(
+            full.clients().format_for_print().unwrap(),
+            compact.clients().format_for_print().unwrap()
+        );
+        assert_eq!(
+            tx_ids(full.transactions().open_disputes()),
+            tx_ids(compact.transactions().open_disputes())
+        );
+    }
+}